@@ -0,0 +1,476 @@
+//! Serve an easy-fs image through FUSE.
+//!
+//! FUSE inode numbers are easy-fs inode ids plus one, since FUSE reserves
+//! 1 for the root. Every `Inode` operation is a transaction of the journal,
+//! so the image is always consistent. It is brought up to date by `fsync`
+//! and when it is unmounted.
+
+use easy_fs::{
+    block_cache_sync_all, DiskInodeType, Inode, Metadata, MAX_FILE_SIZE, NAME_LENGTH_LIMIT,
+};
+use fuser::{
+    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use libc::{c_int, EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR, ENOTEMPTY};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the kernel may cache attributes and entries. Nothing but this
+/// process changes the image while it is mounted.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Milliseconds since the Unix epoch, to be used as the clock of the image.
+pub fn host_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+pub struct EasyFuse {
+    /// Every inode the kernel knows, by FUSE inode number, with the number
+    /// of lookups it has not forgotten yet.
+    inodes: HashMap<u64, (Arc<Inode>, u64)>,
+    uid: u32,
+    gid: u32,
+}
+
+impl EasyFuse {
+    pub fn new(root_inode: Arc<Inode>) -> Self {
+        let mut inodes = HashMap::new();
+        // the root is never forgotten
+        inodes.insert(fuser::FUSE_ROOT_ID, (root_inode, 1));
+        Self {
+            inodes,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    fn inode(&self, ino: u64) -> Result<Arc<Inode>, c_int> {
+        self.inodes
+            .get(&ino)
+            .map(|(inode, _)| Arc::clone(inode))
+            .ok_or(ENOENT)
+    }
+
+    /// Look up `name` in directory `parent`.
+    fn find(&self, parent: u64, name: &OsStr) -> Result<Arc<Inode>, c_int> {
+        let dir = self.inode(parent)?;
+        if !dir.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let inode = name_str(name)
+            .and_then(|name| dir.find(name))
+            .ok_or(ENOENT)?;
+        Ok(inode)
+    }
+
+    /// Count a lookup of `inode` by the kernel, which keeps it in use until
+    /// the kernel forgets it again. An unlinked file is only released then.
+    fn remember(&mut self, inode: Arc<Inode>) -> Arc<Inode> {
+        let (inode, lookups) = self
+            .inodes
+            .entry(inode.inode_id() as u64 + 1)
+            .or_insert((inode, 0));
+        *lookups += 1;
+        Arc::clone(inode)
+    }
+
+    fn attr(&self, metadata: &Metadata) -> FileAttr {
+        // easy-fs has no permissions either
+        let perm = match metadata.type_ {
+            DiskInodeType::File => 0o644,
+            DiskInodeType::Directory => 0o755,
+            DiskInodeType::Symlink => 0o777,
+        };
+        let mtime = UNIX_EPOCH + Duration::from_millis(metadata.mtime);
+        let ctime = UNIX_EPOCH + Duration::from_millis(metadata.ctime);
+        FileAttr {
+            ino: metadata.inode_id as u64 + 1,
+            size: metadata.size as u64,
+            // counted in 512-byte units
+            blocks: metadata.blocks as u64 * (easy_fs::BLOCK_SZ as u64 / 512),
+            atime: mtime,
+            mtime,
+            ctime,
+            crtime: ctime,
+            kind: file_type(metadata.type_),
+            perm,
+            nlink: metadata.nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: easy_fs::BLOCK_SZ as u32,
+            flags: 0,
+        }
+    }
+
+    /// Reply with the entry of a freshly made or found inode.
+    fn reply_entry(&mut self, inode: Result<Arc<Inode>, c_int>, reply: ReplyEntry) {
+        match inode {
+            Ok(inode) => {
+                let inode = self.remember(inode);
+                reply.entry(&TTL, &self.attr(&inode.metadata()), 0);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    /// Make `name` in directory `parent` with `make`, which fails if the
+    /// name is taken or invalid.
+    fn make(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        make: impl FnOnce(&Inode, &str) -> Option<Arc<Inode>>,
+    ) -> Result<Arc<Inode>, c_int> {
+        let dir = self.inode(parent)?;
+        let name = name_str(name).ok_or(EINVAL)?;
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(libc::ENAMETOOLONG);
+        }
+        if dir.find(name).is_some() {
+            return Err(EEXIST);
+        }
+        make(&dir, name).ok_or(EINVAL)
+    }
+
+    /// Guess why removing `name` from `parent` failed.
+    fn remove_error(&self, parent: u64, name: &OsStr, is_dir: bool) -> c_int {
+        let inode = self
+            .inode(parent)
+            .ok()
+            .and_then(|dir| name_str(name).and_then(|name| dir.find(name)));
+        match inode {
+            None => ENOENT,
+            Some(inode) if inode.is_dir() && !is_dir => EISDIR,
+            Some(inode) if !inode.is_dir() && is_dir => ENOTDIR,
+            Some(_) => ENOTEMPTY,
+        }
+    }
+}
+
+/// easy-fs names are UTF-8.
+fn name_str(name: &OsStr) -> Option<&str> {
+    std::str::from_utf8(name.as_bytes()).ok()
+}
+
+fn file_type(type_: DiskInodeType) -> FileType {
+    match type_ {
+        DiskInodeType::File => FileType::RegularFile,
+        DiskInodeType::Directory => FileType::Directory,
+        DiskInodeType::Symlink => FileType::Symlink,
+    }
+}
+
+impl Filesystem for EasyFuse {
+    fn init(&mut self, req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), c_int> {
+        // easy-fs has no owners, so everything belongs to whoever mounted it
+        self.uid = req.uid();
+        self.gid = req.gid();
+        Ok(())
+    }
+
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        if ino == fuser::FUSE_ROOT_ID {
+            return;
+        }
+        if let Some((_, lookups)) = self.inodes.get_mut(&ino) {
+            *lookups = lookups.saturating_sub(nlookup);
+            if *lookups == 0 {
+                self.inodes.remove(&ino);
+            }
+        }
+    }
+
+    fn destroy(&mut self) {
+        // the kernel need not forget everything first, and unlinked files
+        // are only released once nothing holds them
+        self.inodes.clear();
+        block_cache_sync_all();
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let inode = self.find(parent, name);
+        self.reply_entry(inode, reply);
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.inode(ino) {
+            Ok(inode) => reply.attr(&TTL, &self.attr(&inode.metadata())),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    /// Only the size can be changed, everything else is ignored.
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let inode = match self.inode(ino) {
+            Ok(inode) => inode,
+            Err(errno) => return reply.error(errno),
+        };
+        if let Some(size) = size {
+            if inode.is_dir() {
+                return reply.error(EISDIR);
+            }
+            if size > MAX_FILE_SIZE as u64 {
+                return reply.error(libc::EFBIG);
+            }
+            inode.truncate(size as u32);
+        }
+        reply.attr(&TTL, &self.attr(&inode.metadata()));
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.inode(ino).map(|inode| inode.read_link()) {
+            Ok(Some(target)) => reply.data(target.as_bytes()),
+            Ok(None) => reply.error(EINVAL),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let inode = self.make(parent, name, |dir, name| dir.create_dir(name));
+        self.reply_entry(inode, reply);
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let dir = match self.inode(parent) {
+            Ok(dir) => dir,
+            Err(errno) => return reply.error(errno),
+        };
+        if name_str(name).map_or(false, |name| dir.unlink(name)) {
+            reply.ok();
+        } else {
+            reply.error(self.remove_error(parent, name, false));
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let dir = match self.inode(parent) {
+            Ok(dir) => dir,
+            Err(errno) => return reply.error(errno),
+        };
+        if name_str(name).map_or(false, |name| dir.remove_dir(name)) {
+            reply.ok();
+        } else {
+            reply.error(self.remove_error(parent, name, true));
+        }
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        let inode = match target.to_str() {
+            Some(target) => self.make(parent, link_name, |dir, name| {
+                dir.create_symlink(name, target)
+            }),
+            None => Err(EINVAL),
+        };
+        self.reply_entry(inode, reply);
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let (dir, new_dir) = match (self.inode(parent), self.inode(newparent)) {
+            (Ok(dir), Ok(new_dir)) => (dir, new_dir),
+            _ => return reply.error(ENOENT),
+        };
+        let (name, newname) = match (name_str(name), name_str(newname)) {
+            (Some(name), Some(newname)) => (name, newname),
+            _ => return reply.error(EINVAL),
+        };
+        if dir.find(name).is_none() {
+            return reply.error(ENOENT);
+        }
+        let target = new_dir.find(newname);
+        if flags & libc::RENAME_NOREPLACE != 0 && target.is_some() {
+            return reply.error(EEXIST);
+        }
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            return reply.error(EINVAL);
+        }
+        if dir.rename(name, &new_dir, newname) {
+            reply.ok();
+        } else {
+            match target {
+                Some(target) if target.is_dir() => reply.error(ENOTEMPTY),
+                _ => reply.error(EINVAL),
+            }
+        }
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let inode = self.inode(ino).and_then(|inode| {
+            self.make(newparent, newname, |dir, name| {
+                if dir.link(name, &inode) {
+                    Some(Arc::clone(&inode))
+                } else {
+                    None
+                }
+            })
+        });
+        self.reply_entry(inode, reply);
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self.inode(ino) {
+            Ok(_) => reply.opened(0, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let inode = match self.inode(ino) {
+            Ok(inode) => inode,
+            Err(errno) => return reply.error(errno),
+        };
+        let mut buf = vec![0u8; size as usize];
+        let len = inode.read_at(offset as usize, &mut buf);
+        reply.data(&buf[..len]);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let inode = match self.inode(ino) {
+            Ok(inode) => inode,
+            Err(errno) => return reply.error(errno),
+        };
+        match usize::try_from(offset) {
+            Ok(offset) if offset + data.len() <= MAX_FILE_SIZE => {
+                reply.written(inode.write_at(offset, data) as u32)
+            }
+            _ => reply.error(libc::EFBIG),
+        }
+    }
+
+    fn fsync(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, datasync: bool, reply: ReplyEmpty) {
+        match self.inode(ino) {
+            Ok(inode) => {
+                inode.sync(datasync);
+                reply.ok();
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    /// The offsets handed out are those of `Inode::read_dir`.
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let dir = match self.inode(ino) {
+            Ok(dir) if dir.is_dir() => dir,
+            Ok(_) => return reply.error(ENOTDIR),
+            Err(errno) => return reply.error(errno),
+        };
+        let mut offset = offset as usize;
+        while let Some(entry) = dir.read_dir(offset) {
+            let next_offset = entry.next_offset;
+            if reply.add(
+                entry.inode_id as u64 + 1,
+                next_offset as i64,
+                file_type(entry.type_),
+                &entry.name,
+            ) {
+                break;
+            }
+            offset = next_offset;
+        }
+        reply.ok();
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match self.make(parent, name, |dir, name| dir.create(name)) {
+            Ok(inode) => {
+                let inode = self.remember(inode);
+                reply.created(&TTL, &self.attr(&inode.metadata()), 0, 0, 0);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+}
@@ -1,11 +1,24 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+mod fuse;
+mod pack;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use easy_fs::{
+    block_cache_set_capacity, block_cache_sync_all, fsck, BlockDevice, DiskInodeType,
+    EasyFileSystem, Inode,
+};
+#[cfg(test)]
+use easy_fs::{MAX_FILE_SIZE, NAME_LENGTH_LIMIT};
+use pack::{add_manifest, host_entry, invalid_input, PackDir, PackEntry};
+use std::convert::TryFrom;
 use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 const BLOCK_SZ: usize = 512;
+/// Blocks cached in memory, 8 MiB.
+const BLOCK_CACHE_CAPACITY: usize = 16384;
 
 struct BlockFile(Mutex<File>);
 
@@ -30,16 +43,16 @@ impl BlockDevice for BlockFile {
 }
 
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
-}
-
-fn easy_fs_pack() -> std::io::Result<()> {
+    // unlike the kernel, the host has memory to spare
+    block_cache_set_capacity(BLOCK_CACHE_CAPACITY);
     let matches = App::new("EasyFileSystem packer")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("source")
                 .short("s")
                 .long("source")
                 .takes_value(true)
+                .required(true)
                 .help("Executable source dir(with backslash)"),
         )
         .arg(
@@ -47,24 +60,448 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .short("t")
                 .long("target")
                 .takes_value(true)
+                .required(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(manifest_arg())
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .default_value("32M")
+                .help("Size of the image in bytes, with an optional K, M or G suffix, or \"min\""),
+        )
+        .arg(
+            Arg::with_name("inode_bitmap_blocks")
+                .long("inode-bitmap-blocks")
+                .takes_value(true)
+                .default_value("1")
+                .help("Blocks of inode bitmap, each one allowing 4096 inodes"),
+        )
+        .subcommand(
+            SubCommand::with_name("tree")
+                .about("Pack a host directory tree into a new image")
+                .arg(
+                    Arg::with_name("host_dir")
+                        .required(true)
+                        .help("Directory to become the root of the image"),
+                )
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the new image"),
+                )
+                .arg(manifest_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an existing image for inconsistencies")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("repair")
+                        .long("repair")
+                        .help("Fix the problems which can be fixed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory of an existing image")
+                .arg(image_arg())
+                .arg(
+                    Arg::with_name("path")
+                        .default_value("/")
+                        .help("Directory in the image"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Print a file of an existing image")
+                .arg(image_arg())
+                .arg(path_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Copy a file out of an existing image")
+                .arg(image_arg())
+                .arg(path_arg())
+                .arg(
+                    Arg::with_name("host_path")
+                        .required(true)
+                        .help("Where to put the file on the host"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Copy a host file into an existing image, replacing the old one")
+                .arg(image_arg())
+                .arg(
+                    Arg::with_name("host_path")
+                        .required(true)
+                        .help("File on the host"),
+                )
+                .arg(path_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("mount")
+                .about("Serve an existing image through FUSE until it is unmounted")
+                .arg(image_arg())
+                .arg(
+                    Arg::with_name("mountpoint")
+                        .required(true)
+                        .help("Directory to mount the image on"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file from an existing image")
+                .arg(image_arg())
+                .arg(path_arg()),
+        )
         .get_matches();
-    let src_path = matches.value_of("source").unwrap();
-    let target_path = matches.value_of("target").unwrap();
+    let result = match matches.subcommand() {
+        ("tree", Some(tree_matches)) => image_options(&matches).and_then(|options| {
+            easy_fs_pack_tree(
+                tree_matches.value_of("host_dir").unwrap(),
+                tree_matches.value_of("image").unwrap(),
+                tree_matches.value_of("manifest"),
+                &options,
+            )
+        }),
+        ("fsck", Some(fsck_matches)) => easy_fs_fsck(
+            fsck_matches.value_of("image").unwrap(),
+            fsck_matches.is_present("repair"),
+        ),
+        ("ls", Some(ls_matches)) => easy_fs_ls(
+            ls_matches.value_of("image").unwrap(),
+            ls_matches.value_of("path").unwrap(),
+            &mut std::io::stdout(),
+        ),
+        ("cat", Some(cat_matches)) => easy_fs_cat(
+            cat_matches.value_of("image").unwrap(),
+            cat_matches.value_of("path").unwrap(),
+            &mut std::io::stdout(),
+        ),
+        ("get", Some(get_matches)) => File::create(get_matches.value_of("host_path").unwrap())
+            .and_then(|mut host_file| {
+                easy_fs_cat(
+                    get_matches.value_of("image").unwrap(),
+                    get_matches.value_of("path").unwrap(),
+                    &mut host_file,
+                )
+            }),
+        ("put", Some(put_matches)) => easy_fs_put(
+            put_matches.value_of("image").unwrap(),
+            put_matches.value_of("host_path").unwrap(),
+            put_matches.value_of("path").unwrap(),
+        ),
+        ("mount", Some(mount_matches)) => easy_fs_mount(
+            mount_matches.value_of("image").unwrap(),
+            mount_matches.value_of("mountpoint").unwrap(),
+        ),
+        ("rm", Some(rm_matches)) => easy_fs_rm(
+            rm_matches.value_of("image").unwrap(),
+            rm_matches.value_of("path").unwrap(),
+        ),
+        _ => image_options(&matches).and_then(|options| {
+            easy_fs_pack(
+                matches.value_of("source").unwrap(),
+                matches.value_of("target").unwrap(),
+                matches.value_of("manifest"),
+                &options,
+            )
+        }),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn image_arg() -> Arg<'static, 'static> {
+    Arg::with_name("image")
+        .required(true)
+        .help("Path of the image")
+}
+
+fn manifest_arg() -> Arg<'static, 'static> {
+    Arg::with_name("manifest")
+        .short("m")
+        .long("manifest")
+        .takes_value(true)
+        .help("File listing extra host paths to pack, one per line")
+}
+
+fn path_arg() -> Arg<'static, 'static> {
+    Arg::with_name("path")
+        .required(true)
+        .help("Path of the file in the image")
+}
+
+/// Size and layout of a new image.
+struct ImageOptions {
+    /// Total size in bytes, or `None` for the smallest size which fits.
+    size: Option<u64>,
+    inode_bitmap_blocks: u32,
+}
+
+fn image_options(matches: &clap::ArgMatches) -> std::io::Result<ImageOptions> {
+    let size = match matches.value_of("size").unwrap() {
+        "min" => None,
+        size => Some(parse_size(size)?),
+    };
+    let inode_bitmap_blocks = matches.value_of("inode_bitmap_blocks").unwrap();
+    let inode_bitmap_blocks = match inode_bitmap_blocks.parse::<u32>() {
+        Ok(blocks) if blocks > 0 => blocks,
+        _ => {
+            return Err(invalid_input(format!(
+                "{} is not a valid number of inode bitmap blocks",
+                inode_bitmap_blocks
+            )))
+        }
+    };
+    Ok(ImageOptions {
+        size,
+        inode_bitmap_blocks,
+    })
+}
+
+/// Parse a size like "4096", "64K", "32M" or "1G", the suffixes being powers
+/// of 1024.
+fn parse_size(size: &str) -> std::io::Result<u64> {
+    let (digits, shift) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 10),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 20),
+        Some('G') | Some('g') => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| invalid_input(format!("{} is not a valid size", size)))
+}
+
+/// Create `image_path` with room for `min_blocks` blocks at least, format it
+/// and return its root directory.
+fn create_image(
+    image_path: &str,
+    min_blocks: u32,
+    options: &ImageOptions,
+) -> std::io::Result<Arc<Inode>> {
+    let min_size = min_blocks as u64 * BLOCK_SZ as u64;
+    println!(
+        "{} needs {} bytes ({} blocks) at least",
+        image_path, min_size, min_blocks
+    );
+    let size = options.size.unwrap_or(min_size);
+    if size % BLOCK_SZ as u64 != 0 {
+        return Err(invalid_input(format!(
+            "image size {} is not a multiple of the {} byte block size",
+            size, BLOCK_SZ
+        )));
+    }
+    if size < min_size {
+        return Err(invalid_input(format!(
+            "image size {} is too small, {} needs {} bytes",
+            size, image_path, min_size
+        )));
+    }
+    let total_blocks = u32::try_from(size / BLOCK_SZ as u64)
+        .map_err(|_| invalid_input(format!("image size {} is too large", size)))?;
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image_path)?;
+    f.set_len(size)?;
+    let block_file = Arc::new(BlockFile(Mutex::new(f)));
+    let efs = EasyFileSystem::create(block_file, total_blocks, options.inode_bitmap_blocks);
+    Ok(EasyFileSystem::root_inode(&efs))
+}
+
+fn open_image(image_path: &str) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new().read(true).write(true).open(image_path)?;
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+/// Open the image and return its root directory.
+fn open_root(image_path: &str) -> std::io::Result<Arc<Inode>> {
+    let efs = EasyFileSystem::open(open_image(image_path)?);
+    Ok(EasyFileSystem::root_inode(&efs))
+}
+
+/// Look up `path`, relative to the root of the image, without following
+/// symbolic links.
+fn find_path(root_inode: &Arc<Inode>, path: &str) -> std::io::Result<Arc<Inode>> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(Arc::clone(root_inode), |inode, name| inode.find(name))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} not found in image", path)))
+}
+
+/// Split `path` into the directory holding it and its last component.
+fn find_parent(root_inode: &Arc<Inode>, path: &str) -> std::io::Result<(Arc<Inode>, String)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} does not name a directory entry", path),
+        ));
+    }
+    Ok((find_path(root_inode, parent)?, String::from(name)))
+}
+
+/// List the directory `path` of the image, one entry per line with its
+/// size, and directories marked by a trailing "/".
+fn easy_fs_ls(image_path: &str, path: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    let dir = find_path(&open_root(image_path)?, path)?;
+    if !dir.is_dir() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a directory", path),
+        ));
+    }
+    let mut offset = 0;
+    while let Some(entry) = dir.read_dir(offset) {
+        offset = entry.next_offset;
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        let size = dir.find(&entry.name).unwrap().metadata().size;
+        let suffix = if entry.type_ == DiskInodeType::Directory {
+            "/"
+        } else {
+            ""
+        };
+        writeln!(out, "{:>10} {}{}", size, entry.name, suffix)?;
+    }
+    Ok(())
+}
+
+/// Copy the contents of the file `path` of the image to `out`.
+fn easy_fs_cat(image_path: &str, path: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    let file = find_path(&open_root(image_path)?, path)?;
+    if file.is_dir() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is a directory", path),
+        ));
+    }
+    let mut buf = [0u8; BLOCK_SZ];
+    let mut offset = 0;
+    loop {
+        let len = file.read_at(offset, &mut buf);
+        if len == 0 {
+            break;
+        }
+        out.write_all(&buf[..len])?;
+        offset += len;
+    }
+    Ok(())
+}
+
+/// Copy the host file `host_path` to `path` in the image, replacing the
+/// contents of an existing file.
+fn easy_fs_put(image_path: &str, host_path: &str, path: &str) -> std::io::Result<()> {
+    let mut data: Vec<u8> = Vec::new();
+    File::open(host_path)?.read_to_end(&mut data)?;
+    let (parent, name) = find_parent(&open_root(image_path)?, path)?;
+    let file = match parent.find(&name) {
+        Some(file) if file.is_dir() => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is a directory", path),
+            ))
+        }
+        Some(file) => {
+            file.clear();
+            file
+        }
+        None => parent.create(&name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot create {} in image", path),
+            )
+        })?,
+    };
+    file.write_at(0, &data);
+    block_cache_sync_all();
+    Ok(())
+}
+
+/// Remove the file `path` from the image.
+fn easy_fs_rm(image_path: &str, path: &str) -> std::io::Result<()> {
+    let (parent, name) = find_parent(&open_root(image_path)?, path)?;
+    if !parent.unlink(&name) {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not a file in image", path),
+        ));
+    }
+    block_cache_sync_all();
+    Ok(())
+}
+
+fn easy_fs_fsck(image_path: &str, repair: bool) -> std::io::Result<()> {
+    let problems = fsck(open_image(image_path)?, repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    let left = problems
+        .iter()
+        .filter(|problem| !repair || !problem.is_repairable())
+        .count();
+    if problems.is_empty() {
+        println!("{}: clean", image_path);
+    } else if repair {
+        println!(
+            "{}: repaired {} problems",
+            image_path,
+            problems.len() - left
+        );
+    }
+    if left > 0 {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("{} problems left in {}", left, image_path),
+        ));
+    }
+    Ok(())
+}
+
+/// Mount options naming the image in the mount table.
+fn mount_options(image_path: &str) -> Vec<fuser::MountOption> {
+    vec![
+        fuser::MountOption::FSName(String::from(image_path)),
+        fuser::MountOption::DefaultPermissions,
+    ]
+}
+
+/// Serve the image on `mountpoint` until it is unmounted.
+fn easy_fs_mount(image_path: &str, mountpoint: &str) -> std::io::Result<()> {
+    let efs = EasyFileSystem::open(open_image(image_path)?);
+    efs.lock().set_clock(fuse::host_clock);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    fuser::mount2(
+        fuse::EasyFuse::new(root_inode),
+        mountpoint,
+        &mount_options(image_path),
+    )
+}
+
+fn easy_fs_pack(
+    src_path: &str,
+    target_path: &str,
+    manifest_path: Option<&str>,
+    options: &ImageOptions,
+) -> std::io::Result<()> {
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
-    let block_file = Arc::new(BlockFile(Mutex::new({
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(32 * 2048 * 512).unwrap();
-        f
-    })));
-    // 32MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 32 * 2048, 1);
-    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
         .into_iter()
@@ -74,16 +511,22 @@ fn easy_fs_pack() -> std::io::Result<()> {
             name_with_ext
         })
         .collect();
+    let mut root = PackDir::default();
     for app in apps {
-        // load app data from host file system
-        let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
-        let mut all_data: Vec<u8> = Vec::new();
-        host_file.read_to_end(&mut all_data).unwrap();
-        // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
-        // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+        let host_path = Path::new(target_path).join(&app);
+        let size = std::fs::metadata(&host_path)?.len();
+        root.add(&app, PackEntry::File { host_path, size })?;
+    }
+    if let Some(manifest_path) = manifest_path {
+        add_manifest(&mut root, manifest_path)?;
     }
+    let root_inode = create_image(
+        &format!("{}{}", target_path, "fs.img"),
+        root.min_image_blocks(options.inode_bitmap_blocks)?,
+        options,
+    )?;
+    root.write_to(&root_inode)?;
+    block_cache_sync_all();
     // list apps
     // for app in root_inode.ls() {
     //     println!("{}", app);
@@ -91,8 +534,53 @@ fn easy_fs_pack() -> std::io::Result<()> {
     Ok(())
 }
 
+/// Pack the host directory `host_dir` and the paths listed in the manifest
+/// into a new image, keeping names, sizes and symbolic links.
+fn easy_fs_pack_tree(
+    host_dir: &str,
+    image_path: &str,
+    manifest_path: Option<&str>,
+    options: &ImageOptions,
+) -> std::io::Result<()> {
+    let mut root = match host_entry(Path::new(host_dir))? {
+        PackEntry::Dir(dir) => dir,
+        _ => return Err(invalid_input(format!("{} is not a directory", host_dir))),
+    };
+    if let Some(manifest_path) = manifest_path {
+        add_manifest(&mut root, manifest_path)?;
+    }
+    let root_inode = create_image(
+        image_path,
+        root.min_image_blocks(options.inode_bitmap_blocks)?,
+        options,
+    )?;
+    root.write_to(&root_inode)?;
+    block_cache_sync_all();
+    Ok(())
+}
+
+/// The block cache is global and keyed by block id only, so tests sharing
+/// `target/fs.img` must not run concurrently.
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// A fresh `target/fs.img` of `blocks` blocks, all zero.
+#[cfg(test)]
+fn test_image(blocks: u64) -> Arc<BlockFile> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/fs.img")
+        .unwrap();
+    f.set_len(blocks * BLOCK_SZ as u64).unwrap();
+    Arc::new(BlockFile(Mutex::new(f)))
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
@@ -153,3 +641,993 @@ fn efs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn efs_dir_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.ls().is_empty());
+    assert_eq!(root_inode.find("..").unwrap().inode_id(), 0);
+
+    let home = root_inode.create_dir("home").unwrap();
+    assert!(home.is_dir());
+    assert!(root_inode.create_dir("home").is_none());
+    let a = home.create_dir("a").unwrap();
+    let notes = a.create("notes.txt").unwrap();
+    assert!(!notes.is_dir());
+    notes.write_at(0, b"hello");
+    // walk back up through ".."
+    let up = a.find("..").unwrap().find("..").unwrap();
+    assert_eq!(up.inode_id(), root_inode.inode_id());
+    assert_eq!(a.find(".").unwrap().inode_id(), a.inode_id());
+    assert!(notes.find("x").is_none());
+
+    // only empty directories can be removed
+    assert!(!home.remove_dir("a"));
+    assert!(!a.remove_dir("notes.txt"));
+    assert!(!a.remove_dir(".."));
+    assert!(!root_inode.remove_dir("missing"));
+    notes.clear();
+    let b = home.create_dir("b").unwrap();
+    assert!(home.remove_dir("b"));
+    assert!(home.find("b").is_none());
+    assert_eq!(home.ls(), vec!["a"]);
+    // a listing can be resumed and skips the freed slot
+    let mut offset = 0;
+    let mut entries = Vec::new();
+    while let Some(entry) = home.read_dir(offset) {
+        entries.push((entry.name, entry.inode_id, entry.type_));
+        offset = entry.next_offset;
+    }
+    assert_eq!(
+        entries,
+        vec![
+            (String::from("."), home.inode_id(), DiskInodeType::Directory),
+            (String::from(".."), 0, DiskInodeType::Directory),
+            (String::from("a"), a.inode_id(), DiskInodeType::Directory),
+        ]
+    );
+    assert!(home.read_dir(offset).is_none());
+    assert!(notes.read_dir(0).is_none());
+    // the freed slot and inode are reused, once the inode is no longer in use
+    let b_id = b.inode_id();
+    drop(b);
+    let c = home.create_dir("c").unwrap();
+    assert_eq!(c.inode_id(), b_id);
+    assert_eq!(home.ls(), vec!["a", "c"]);
+    Ok(())
+}
+
+#[test]
+fn efs_unlink_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    // small enough that the loop below runs out of space unless blocks are freed
+    EasyFileSystem::create(block_file.clone(), 2048, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let data = vec![0x5au8; 200 * BLOCK_SZ];
+    let dir = root_inode.create_dir("dir").unwrap();
+    assert!(!root_inode.unlink("dir"));
+    for _ in 0..20 {
+        let file = root_inode.create("big").unwrap();
+        assert_eq!(file.write_at(0, &data), data.len());
+        assert!(!root_inode.remove_dir("big"));
+        assert!(root_inode.unlink("big"));
+        assert!(root_inode.find("big").is_none());
+        assert!(!root_inode.unlink("big"));
+    }
+    assert_eq!(root_inode.ls(), vec!["dir"]);
+    assert!(dir.create("nested").is_some());
+    assert!(dir.unlink("nested"));
+    assert!(root_inode.remove_dir("dir"));
+    Ok(())
+}
+
+#[test]
+fn efs_inode_table_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 8192, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(Arc::ptr_eq(&root_inode, &EasyFileSystem::root_inode(&efs)));
+
+    // every lookup of a file shares one inode, and so sees the same size
+    let file = root_inode.create("file").unwrap();
+    let found = root_inode.find("file").unwrap();
+    assert!(Arc::ptr_eq(&file, &found));
+    let data = vec![0x5au8; 20 * BLOCK_SZ];
+    file.write_at(0, &data);
+    assert_eq!(found.metadata().size, data.len() as u32);
+    drop(found);
+
+    // an unlinked file stays readable until its last user drops it
+    assert!(root_inode.unlink("file"));
+    assert!(root_inode.find("file").is_none());
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf), data.len());
+    assert_eq!(buf, data);
+    let other = root_inode.create("other").unwrap();
+    assert_ne!(other.inode_id(), file.inode_id());
+    let file_id = file.inode_id();
+    drop(file);
+    assert_eq!(root_inode.create("reused").unwrap().inode_id(), file_id);
+
+    // nothing is made in a removed directory, even by its last user
+    let dir = root_inode.create_dir("dir").unwrap();
+    assert!(root_inode.remove_dir("dir"));
+    assert!(dir.create("a").is_none());
+    assert!(dir.create_dir("b").is_none());
+    assert!(!dir.link("c", &other));
+    assert!(!root_inode.rename("other", &dir, "d"));
+    drop(dir);
+
+    block_cache_sync_all();
+    assert_eq!(fsck(block_file, false), vec![]);
+    Ok(())
+}
+
+#[test]
+fn efs_truncate_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    // small enough that leaked blocks would run out during the loop
+    EasyFileSystem::create(block_file.clone(), 2048, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let data: Vec<u8> = (0..300 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    let file = root_inode.create("log").unwrap();
+    let mut buf = vec![0u8; 300 * BLOCK_SZ];
+    for _ in 0..20 {
+        assert_eq!(file.write_at(0, &data), data.len());
+        // through the indirect2, indirect1 and direct ranges
+        for new_size in [200 * BLOCK_SZ + 100, 100 * BLOCK_SZ, 10 * BLOCK_SZ + 1] {
+            file.truncate(new_size as u32);
+            assert_eq!(file.metadata().size as usize, new_size);
+            assert_eq!(file.read_at(0, &mut buf), new_size);
+            assert_eq!(&buf[..new_size], &data[..new_size]);
+        }
+        // growing again exposes zeroes, not the old contents
+        file.truncate(20 * BLOCK_SZ as u32);
+        assert_eq!(file.read_at(0, &mut buf), 20 * BLOCK_SZ);
+        assert_eq!(&buf[..10 * BLOCK_SZ + 1], &data[..10 * BLOCK_SZ + 1]);
+        assert!(buf[10 * BLOCK_SZ + 1..20 * BLOCK_SZ]
+            .iter()
+            .all(|b| *b == 0));
+        file.truncate(0);
+        assert_eq!(file.read_at(0, &mut buf), 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn efs_sparse_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(2048);
+    // far too small for the files below if their holes took blocks
+    EasyFileSystem::create(block_file.clone(), 2048, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+
+    let file = root_inode.create("sparse").unwrap();
+    let far = 8000 * BLOCK_SZ;
+    assert_eq!(file.write_at(far, b"tail"), 4);
+    let metadata = file.metadata();
+    assert_eq!(metadata.size as usize, far + 4);
+    // the data block, indirect2 and one low-level indirect1 block
+    assert_eq!(metadata.blocks, 3);
+    let mut buf = vec![0xffu8; 2 * BLOCK_SZ];
+    assert_eq!(file.read_at(far - BLOCK_SZ, &mut buf), BLOCK_SZ + 4);
+    assert!(buf[..BLOCK_SZ].iter().all(|&byte| byte == 0));
+    assert_eq!(&buf[BLOCK_SZ..BLOCK_SZ + 4], b"tail");
+
+    // growing leaves a hole, writing into one fills just what is written
+    file.truncate(16000 * BLOCK_SZ as u32);
+    assert_eq!(file.metadata().blocks, 3);
+    file.write_at(30 * BLOCK_SZ + 10, &[7u8; BLOCK_SZ]);
+    assert_eq!(file.metadata().blocks, 6);
+    assert_eq!(file.read_at(30 * BLOCK_SZ, &mut buf), buf.len());
+    assert!(buf[..10].iter().all(|&byte| byte == 0));
+    assert!(buf[10..BLOCK_SZ + 10].iter().all(|&byte| byte == 7));
+    assert!(buf[BLOCK_SZ + 10..].iter().all(|&byte| byte == 0));
+
+    // shrinking frees the blocks past the end and clears their entries
+    file.truncate((30 * BLOCK_SZ + 20) as u32);
+    assert_eq!(file.metadata().blocks, 2);
+    file.truncate(far as u32 + 4);
+    assert_eq!(file.metadata().blocks, 2);
+    assert_eq!(file.read_at(far, &mut buf), 4);
+    assert!(buf[..4].iter().all(|&byte| byte == 0));
+    assert_eq!(file.read_at(30 * BLOCK_SZ, &mut buf[..BLOCK_SZ]), BLOCK_SZ);
+    assert!(buf[10..20].iter().all(|&byte| byte == 7));
+    assert!(buf[20..BLOCK_SZ].iter().all(|&byte| byte == 0));
+
+    let other = root_inode.create("other").unwrap();
+    other.truncate(16000 * BLOCK_SZ as u32);
+    // nothing maps past the double indirect block
+    assert!(!other.truncate(MAX_FILE_SIZE as u32 + 1));
+    assert_eq!(other.write_at(100 << 20, b"far"), 0);
+    assert_eq!(other.write_at(MAX_FILE_SIZE - 1, b"end"), 1);
+    assert_eq!(other.metadata().size as usize, MAX_FILE_SIZE);
+    block_cache_sync_all();
+    assert_eq!(fsck(block_file.clone(), false), vec![]);
+    Ok(())
+}
+
+#[test]
+fn efs_rename_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let a = root_inode.create_dir("a").unwrap();
+    let b = root_inode.create_dir("b").unwrap();
+    let file = a.create("f").unwrap();
+    file.write_at(0, b"data");
+
+    // within a directory, and onto itself
+    assert!(a.rename("f", &a, "g"));
+    assert!(a.find("f").is_none());
+    assert_eq!(a.find("g").unwrap().inode_id(), file.inode_id());
+    assert!(a.rename("g", &a, "g"));
+    assert!(!a.rename("missing", &a, "x"));
+    assert!(!a.rename("g", &a, ".."));
+
+    // across directories, replacing an existing file
+    let old = b.create("g").unwrap();
+    old.write_at(0, b"old");
+    assert!(a.rename("g", &b, "g"));
+    assert!(a.ls().is_empty());
+    let moved = b.find("g").unwrap();
+    assert_eq!(moved.inode_id(), file.inode_id());
+    let mut buf = [0u8; 8];
+    assert_eq!(moved.read_at(0, &mut buf), 4);
+    assert_eq!(&buf[..4], b"data");
+    // the replaced inode is reused for the next file, once it is dropped
+    let old_id = old.inode_id();
+    drop(old);
+    assert_eq!(root_inode.create("h").unwrap().inode_id(), old_id);
+
+    // a directory cannot replace a file, nor be moved below itself
+    let c = a.create_dir("c").unwrap();
+    assert!(!a.rename("c", &b, "g"));
+    assert!(!b.rename("g", &a, "c"));
+    assert!(!root_inode.rename("a", &c, "a"));
+    assert!(!root_inode.rename("a", &a, "x"));
+
+    // moving a directory fixes up ".." and the link counts
+    assert!(a.rename("c", &b, "c"));
+    assert_eq!(c.find("..").unwrap().inode_id(), b.inode_id());
+    assert_eq!(a.nlink(), 2);
+    assert_eq!(b.nlink(), 3);
+    // and an empty directory can be replaced
+    let d = a.create_dir("d").unwrap();
+    assert!(!a.rename("d", &b, "g"));
+    c.create("x").unwrap();
+    assert!(!a.rename("d", &b, "c"));
+    assert!(c.unlink("x"));
+    assert!(a.rename("d", &b, "c"));
+    assert_eq!(b.find("c").unwrap().inode_id(), d.inode_id());
+    assert_eq!(d.find("..").unwrap().inode_id(), b.inode_id());
+    assert_eq!(a.nlink(), 2);
+    assert_eq!(b.nlink(), 3);
+    Ok(())
+}
+
+#[test]
+fn efs_long_name_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode
+        .create(&"x".repeat(NAME_LENGTH_LIMIT + 1))
+        .is_none());
+    // names of every length, spread over several blocks
+    let names: Vec<String> = (1..=NAME_LENGTH_LIMIT)
+        .step_by(7)
+        .map(|len| format!("{:0>width$}", len, width = len))
+        .collect();
+    for name in names.iter() {
+        let inode = root_inode.create(name).unwrap();
+        inode.write_at(0, name.as_bytes());
+    }
+    assert_eq!(root_inode.ls(), names);
+    let size = root_inode.metadata().size;
+    assert!(size as usize > BLOCK_SZ);
+    // removing every other entry frees room that is handed out again
+    for name in names.iter().step_by(2) {
+        assert!(root_inode.unlink(name));
+    }
+    for name in names.iter().step_by(2) {
+        root_inode.create(name).unwrap();
+    }
+    assert_eq!(root_inode.metadata().size, size);
+    let mut listed = root_inode.ls();
+    listed.sort();
+    let mut expected = names.clone();
+    expected.sort();
+    assert_eq!(listed, expected);
+    for name in names.iter().skip(1).step_by(2) {
+        let mut buf = vec![0u8; name.len()];
+        root_inode.find(name).unwrap().read_at(0, &mut buf);
+        assert_eq!(buf, name.as_bytes());
+    }
+    // a rename may need a bigger entry than the old one
+    let long_name = "y".repeat(NAME_LENGTH_LIMIT);
+    assert!(root_inode.rename(&names[0], &root_inode, &long_name));
+    assert!(root_inode.find(&names[0]).is_none());
+    assert!(root_inode.find(&long_name).is_some());
+    Ok(())
+}
+
+#[test]
+fn efs_link_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.nlink(), 2);
+    let dir = root_inode.create_dir("dir").unwrap();
+    assert_eq!(dir.nlink(), 2);
+    assert_eq!(root_inode.nlink(), 3);
+
+    let file = root_inode.create("a").unwrap();
+    file.write_at(0, b"shared");
+    assert!(dir.link("b", &file));
+    assert!(!dir.link("b", &file));
+    assert!(!root_inode.link("dir2", &dir));
+    assert_eq!(file.nlink(), 2);
+
+    // the data survives as long as one name is left
+    assert!(root_inode.unlink("a"));
+    assert_eq!(file.nlink(), 1);
+    let b = dir.find("b").unwrap();
+    assert_eq!(b.inode_id(), file.inode_id());
+    let mut buffer = [0u8; 16];
+    let len = b.read_at(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"shared");
+    assert!(dir.unlink("b"));
+    let file_id = file.inode_id();
+    drop((file, b));
+    assert_eq!(root_inode.create("c").unwrap().inode_id(), file_id);
+
+    assert!(root_inode.remove_dir("dir"));
+    assert_eq!(root_inode.nlink(), 2);
+    Ok(())
+}
+
+#[test]
+fn efs_symlink_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let busybox = root_inode.create("busybox").unwrap();
+    let ls = root_inode.create_symlink("ls", "busybox").unwrap();
+    assert!(ls.is_symlink());
+    assert!(!busybox.is_symlink());
+    assert_eq!(ls.read_link().unwrap(), "busybox");
+    assert!(busybox.read_link().is_none());
+    assert!(root_inode.create_symlink("ls", "other").is_none());
+    assert!(root_inode.create_symlink("empty", "").is_none());
+    // a long target spills over into a second block
+    let long_target = "a/".repeat(300);
+    let long = root_inode.create_symlink("long", &long_target).unwrap();
+    assert_eq!(long.read_link().unwrap(), long_target);
+    assert!(root_inode.unlink("ls"));
+    assert!(root_inode.find("busybox").is_some());
+    Ok(())
+}
+
+#[test]
+fn efs_metadata_test() -> std::io::Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    efs.lock().set_clock(|| NOW.load(Ordering::SeqCst));
+    let root_inode = EasyFileSystem::root_inode(&efs);
+
+    NOW.store(10, Ordering::SeqCst);
+    let file = root_inode.create("file").unwrap();
+    let metadata = file.metadata();
+    assert_eq!(metadata.type_, DiskInodeType::File);
+    assert_eq!((metadata.size, metadata.nlink), (0, 1));
+    assert_eq!((metadata.mtime, metadata.ctime), (10, 10));
+    assert_eq!(root_inode.metadata().mtime, 10);
+    assert_eq!(root_inode.metadata().type_, DiskInodeType::Directory);
+
+    NOW.store(20, Ordering::SeqCst);
+    file.write_at(0, &[1u8; 700]);
+    let metadata = file.metadata();
+    assert_eq!(metadata.size, 700);
+    assert_eq!((metadata.mtime, metadata.ctime), (20, 20));
+
+    // a new link only changes the status
+    NOW.store(30, Ordering::SeqCst);
+    assert!(root_inode.link("file2", &file));
+    let metadata = file.metadata();
+    assert_eq!(metadata.nlink, 2);
+    assert_eq!((metadata.mtime, metadata.ctime), (20, 30));
+    Ok(())
+}
+
+/// An in-memory disk which silently drops every write once `writes_left`
+/// runs out, as if the machine had lost power.
+#[cfg(test)]
+struct CrashingDisk {
+    blocks: Mutex<Vec<u8>>,
+    writes_left: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl CrashingDisk {
+    fn new(image: Vec<u8>) -> Self {
+        Self {
+            blocks: Mutex::new(image),
+            writes_left: std::sync::atomic::AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    fn image(&self) -> Vec<u8> {
+        self.blocks.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashingDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let blocks = self.blocks.lock().unwrap();
+        buf.copy_from_slice(&blocks[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        use std::sync::atomic::Ordering;
+        let mut blocks = self.blocks.lock().unwrap();
+        let alive = self
+            .writes_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if alive {
+            blocks[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ].copy_from_slice(buf);
+        }
+    }
+
+    fn handle_irq(&self) {
+        unimplemented!();
+    }
+}
+
+/// Cut the power after every possible number of writes during `op` and
+/// check that the reopened image is the one from before or after it.
+#[cfg(test)]
+fn crash_at_every_write(image: &[u8], op: fn(&Inode)) {
+    use std::sync::atomic::Ordering;
+    // blocks outside the journal, where the two states have to be found
+    let home = |image: &[u8]| {
+        let mut home = image[..BLOCK_SZ].to_vec();
+        home.extend_from_slice(&image[(1 + easy_fs::JOURNAL_BLOCKS as usize) * BLOCK_SZ..]);
+        home
+    };
+    let disk = Arc::new(CrashingDisk::new(image.to_vec()));
+    let efs = EasyFileSystem::open(disk.clone());
+    op(&EasyFileSystem::root_inode(&efs));
+    block_cache_sync_all();
+    let after = home(&disk.image());
+    assert_ne!(after, home(image));
+    let mut crashed_midway = false;
+    for writes in 0.. {
+        let disk = Arc::new(CrashingDisk::new(image.to_vec()));
+        let efs = EasyFileSystem::open(disk.clone());
+        disk.writes_left.store(writes, Ordering::SeqCst);
+        op(&EasyFileSystem::root_inode(&efs));
+        block_cache_sync_all();
+        let finished = disk.writes_left.load(Ordering::SeqCst) > 0;
+        // reboot
+        let rebooted = Arc::new(CrashingDisk::new(disk.image()));
+        EasyFileSystem::open(rebooted.clone());
+        let state = home(&rebooted.image());
+        if state == after {
+            crashed_midway |= !finished;
+        } else {
+            assert_eq!(state, home(image), "torn update after {} writes", writes);
+        }
+        if finished {
+            break;
+        }
+    }
+    // the home writes of the transaction must have been interrupted at least once
+    assert!(crashed_midway);
+}
+
+#[test]
+fn efs_journal_test() {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let disk = Arc::new(CrashingDisk::new(vec![0u8; 2048 * BLOCK_SZ]));
+    let efs = EasyFileSystem::create(disk.clone(), 2048, 1);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dir = root_inode.create_dir("dir").unwrap();
+    root_inode
+        .create("file")
+        .unwrap()
+        .write_at(0, &[0x5au8; 3 * BLOCK_SZ]);
+    for i in 0..8 {
+        dir.create(format!("entry{}", i).as_str()).unwrap();
+    }
+    block_cache_sync_all();
+    let image = disk.image();
+    crash_at_every_write(&image, |root_inode| {
+        root_inode.create("new").unwrap();
+    });
+    crash_at_every_write(&image, |root_inode| {
+        let dir = root_inode.find("dir").unwrap();
+        assert!(root_inode.rename("file", &dir, "moved"));
+    });
+    crash_at_every_write(&image, |root_inode| {
+        root_inode.create_dir("newdir").unwrap();
+    });
+}
+
+#[test]
+fn efs_block_cache_test() {
+    use std::sync::atomic::Ordering;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let disk = Arc::new(CrashingDisk::new(vec![0u8; 2048 * BLOCK_SZ]));
+    let efs = EasyFileSystem::create(disk.clone(), 2048, 1);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let file = root_inode.create("log").unwrap();
+    // small writes stay in the cache until they are synced
+    let writes = |disk: &CrashingDisk| usize::MAX - disk.writes_left.load(Ordering::SeqCst);
+    let before = writes(&disk);
+    for i in 0..100 {
+        file.write_at(i * 10, b"0123456789");
+    }
+    assert_eq!(writes(&disk), before);
+    block_cache_sync_all();
+    // the journal header twice, then each of the few blocks changed twice
+    assert!(writes(&disk) - before <= 2 + 2 * 8);
+
+    // a cache smaller than a single transaction still works
+    block_cache_set_capacity(2);
+    let data: Vec<u8> = (0..100 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    let big = root_inode.create("big").unwrap();
+    assert_eq!(big.write_at(0, &data), data.len());
+    big.truncate(BLOCK_SZ as u32 * 3 / 2);
+    block_cache_set_capacity(BLOCK_CACHE_CAPACITY);
+    block_cache_sync_all();
+    let efs = EasyFileSystem::open(disk.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let mut buf = vec![0u8; 2 * BLOCK_SZ];
+    assert_eq!(
+        root_inode.find("big").unwrap().read_at(0, &mut buf),
+        BLOCK_SZ * 3 / 2
+    );
+    assert_eq!(&buf[..BLOCK_SZ * 3 / 2], &data[..BLOCK_SZ * 3 / 2]);
+    assert_eq!(root_inode.find("log").unwrap().metadata().size, 1000);
+    assert_eq!(fsck(disk, false), vec![]);
+}
+
+#[test]
+fn efs_sync_test() {
+    use std::sync::atomic::Ordering;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let disk = Arc::new(CrashingDisk::new(vec![0u8; 2048 * BLOCK_SZ]));
+    let efs = EasyFileSystem::create(disk.clone(), 2048, 1);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let writes = |disk: &CrashingDisk| usize::MAX - disk.writes_left.load(Ordering::SeqCst);
+    let file = root_inode.create("file").unwrap();
+    file.write_at(0, &[1u8; 3 * BLOCK_SZ]);
+    let before = writes(&disk);
+    file.sync(false);
+    assert!(writes(&disk) > before);
+    let durable = |disk: &Arc<CrashingDisk>| {
+        let efs = EasyFileSystem::open(Arc::new(CrashingDisk::new(disk.image())));
+        let size = EasyFileSystem::root_inode(&efs)
+            .find("file")
+            .map(|file| file.metadata().size);
+        // back to the disk of this test
+        EasyFileSystem::open(disk.clone());
+        size
+    };
+    assert_eq!(durable(&disk), Some(3 * BLOCK_SZ as u32));
+    let root_inode = EasyFileSystem::root_inode(&EasyFileSystem::open(disk.clone()));
+    let file = root_inode.find("file").unwrap();
+
+    // nothing to do for a clean file
+    let before = writes(&disk);
+    file.sync(false);
+    assert_eq!(writes(&disk), before);
+    // a new link only changes metadata
+    assert!(root_inode.link("other", &file));
+    file.sync(true);
+    assert_eq!(writes(&disk), before);
+    file.sync(false);
+    assert!(writes(&disk) > before);
+    // but a smaller size has to reach the disk
+    file.truncate(BLOCK_SZ as u32);
+    let before = writes(&disk);
+    file.sync(true);
+    assert!(writes(&disk) > before);
+    assert_eq!(durable(&disk), Some(BLOCK_SZ as u32));
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    use easy_fs::FsckProblem;
+    use std::convert::TryInto;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let a = root_inode.create("a").unwrap();
+    a.write_at(0, &[1u8; 3 * BLOCK_SZ]);
+    let d = root_inode.create_dir("d").unwrap();
+    let b = d.create("b").unwrap();
+    b.write_at(0, &[2u8; 2 * BLOCK_SZ]);
+    assert!(d.link("c", &a));
+    let ghost = root_inode.create("ghost").unwrap();
+    let big = root_inode.create("big").unwrap();
+    big.write_at(0, &[3u8; 10 * BLOCK_SZ]);
+    assert_eq!(fsck(block_file.clone(), false), vec![]);
+
+    // corrupt the image behind the back of easy-fs
+    let read_word = |block_id: usize, offset: usize| {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id, &mut block);
+        u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+    };
+    let write_word = |block_id: usize, offset: usize, word: u32| {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id, &mut block);
+        block[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+        block_file.write_block(block_id, &block);
+    };
+    let journal_blocks = read_word(0, 8) as usize;
+    let inode_bitmap = 1 + journal_blocks;
+    let inode_area = inode_bitmap + read_word(0, 12) as usize;
+    let data_bitmap = inode_area + read_word(0, 16) as usize;
+    let data_area = (data_bitmap + read_word(0, 20) as usize) as u32;
+    // a DiskInode takes 128 bytes, with direct[0] at 4, indirect1 at 96
+    // and nlink at 104
+    let inode_pos = |inode_id: u32| {
+        (
+            inode_area + inode_id as usize / 4,
+            inode_id as usize % 4 * 128,
+        )
+    };
+    let (block_id, offset) = inode_pos(a.inode_id());
+    write_word(block_id, offset + 104, 5);
+    // a size past the blocks is a hole, a block outside the data area is not
+    let (block_id, offset) = inode_pos(big.inode_id());
+    write_word(block_id, offset, 40 * BLOCK_SZ as u32);
+    write_word(block_id, offset + 96, 1);
+    let ghost_word = inode_bitmap * BLOCK_SZ + ghost.inode_id() as usize / 32 * 4;
+    let ghost_bits = read_word(ghost_word / BLOCK_SZ, ghost_word % BLOCK_SZ);
+    write_word(
+        ghost_word / BLOCK_SZ,
+        ghost_word % BLOCK_SZ,
+        ghost_bits & !(1 << (ghost.inode_id() % 32)),
+    );
+    write_word(data_bitmap, 900 / 32 * 4, 1 << (900 % 32));
+    let (block_id, offset) = inode_pos(a.inode_id());
+    let a_block = read_word(block_id, offset + 4);
+    let (block_id, offset) = inode_pos(b.inode_id());
+    let b_block = read_word(block_id, offset + 4);
+    write_word(block_id, offset + 4, a_block);
+
+    let expected = vec![
+        FsckProblem::DanglingEntry {
+            dir_id: 0,
+            name: String::from("ghost"),
+            inode_id: ghost.inode_id(),
+        },
+        FsckProblem::SizeMismatch {
+            inode_id: big.inode_id(),
+            size: 40 * BLOCK_SZ as u32,
+            valid_size: 23 * BLOCK_SZ as u32,
+        },
+        FsckProblem::DuplicateBlock {
+            block_id: a_block,
+            inode_ids: (a.inode_id(), b.inode_id()),
+        },
+        FsckProblem::WrongLinkCount {
+            inode_id: a.inode_id(),
+            nlink: 5,
+            links: 2,
+        },
+        FsckProblem::LeakedBlock(b_block),
+        FsckProblem::LeakedBlock(data_area + 900),
+    ];
+    assert_eq!(fsck(block_file.clone(), false), expected);
+    assert_eq!(fsck(block_file.clone(), true), expected);
+    // two inodes sharing a block are left for a human to sort out
+    assert_eq!(fsck(block_file.clone(), false), vec![expected[2].clone()]);
+
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.ls(), vec!["a", "d", "big"]);
+    assert_eq!(root_inode.find("a").unwrap().nlink(), 2);
+    assert_eq!(
+        root_inode.find("big").unwrap().metadata().size,
+        23 * BLOCK_SZ as u32
+    );
+
+    write_word(0, 0, 0);
+    assert_eq!(
+        fsck(block_file.clone(), false),
+        vec![FsckProblem::BadSuperBlock]
+    );
+    Ok(())
+}
+
+#[test]
+fn efs_image_commands_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    let efs = EasyFileSystem::create(block_file, 4096, 1);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    root_inode.create_dir("logs").unwrap();
+    let log = root_inode.find("logs").unwrap().create("boot.log").unwrap();
+    log.write_at(0, b"kernel log\n");
+    drop(efs);
+
+    let image = "target/fs.img";
+    let mut out = Vec::new();
+    easy_fs_cat(image, "/logs/boot.log", &mut out)?;
+    assert_eq!(out, b"kernel log\n");
+    let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    std::fs::write("target/put_test", &data)?;
+    easy_fs_put(image, "target/put_test", "/logs/data")?;
+    // a second put replaces the contents
+    std::fs::write("target/put_test", &data[..1000])?;
+    easy_fs_put(image, "target/put_test", "logs/data")?;
+    let mut out = Vec::new();
+    easy_fs_ls(image, "/logs", &mut out)?;
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "        11 boot.log\n      1000 data\n"
+    );
+    let mut out = Vec::new();
+    easy_fs_ls(image, "/", &mut out)?;
+    assert_eq!(String::from_utf8(out).unwrap(), "       512 logs/\n");
+    let mut out = Vec::new();
+    easy_fs_cat(image, "logs/data", &mut out)?;
+    assert_eq!(out, &data[..1000]);
+
+    easy_fs_rm(image, "/logs/data")?;
+    assert_eq!(
+        easy_fs_cat(image, "/logs/data", &mut Vec::new())
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+    assert!(easy_fs_rm(image, "/logs").is_err());
+    assert!(easy_fs_put(image, "target/put_test", "/missing/data").is_err());
+    assert_eq!(fsck(open_image(image)?, false), vec![]);
+    Ok(())
+}
+
+#[test]
+fn efs_pack_size_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    assert_eq!(parse_size("4096")?, 4096);
+    assert_eq!(parse_size("64K")?, 64 << 10);
+    assert_eq!(parse_size("32M")?, 32 << 20);
+    assert_eq!(parse_size("1g")?, 1 << 30);
+    assert!(parse_size("M").is_err());
+    assert!(parse_size("12T").is_err());
+
+    let _ = std::fs::remove_dir_all("target/pack_test");
+    std::fs::create_dir_all("target/pack_test/src")?;
+    std::fs::create_dir_all("target/pack_test/bin")?;
+    // enough names to spill the root directory into more blocks, and a file
+    // large enough to need the doubly indirect block
+    let mut apps: Vec<(String, usize)> = (0..40)
+        .map(|i| (format!("app_with_a_long_name_{:02}", i), i * 700))
+        .collect();
+    apps.push((String::from("big"), 100 * 1024));
+    for (app, size) in apps.iter() {
+        std::fs::write(format!("target/pack_test/src/{}.rs", app), b"")?;
+        let data: Vec<u8> = (0..*size).map(|i| (i % 251) as u8).collect();
+        std::fs::write(format!("target/pack_test/bin/{}", app), data)?;
+    }
+    let min = ImageOptions {
+        size: None,
+        inode_bitmap_blocks: 1,
+    };
+    easy_fs_pack("target/pack_test/src", "target/pack_test/bin/", None, &min)?;
+    let image = "target/pack_test/bin/fs.img";
+    let min_size = std::fs::metadata(image)?.len();
+    assert_eq!(fsck(open_image(image)?, false), vec![]);
+    for (app, size) in apps.iter() {
+        let mut out = Vec::new();
+        easy_fs_cat(image, app, &mut out)?;
+        assert_eq!(out.len(), *size);
+    }
+
+    let too_small = ImageOptions {
+        size: Some(min_size - BLOCK_SZ as u64),
+        inode_bitmap_blocks: 1,
+    };
+    let err = easy_fs_pack(
+        "target/pack_test/src",
+        "target/pack_test/bin/",
+        None,
+        &too_small,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let unaligned = ImageOptions {
+        size: Some(min_size + 1),
+        inode_bitmap_blocks: 1,
+    };
+    assert!(easy_fs_pack(
+        "target/pack_test/src",
+        "target/pack_test/bin/",
+        None,
+        &unaligned
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn efs_pack_tree_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let _ = std::fs::remove_dir_all("target/tree_test");
+    std::fs::create_dir_all("target/tree_test/root/bin")?;
+    std::fs::create_dir_all("target/tree_test/root/empty")?;
+    std::fs::create_dir_all("target/tree_test/extra/docs")?;
+    let data: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+    std::fs::write("target/tree_test/root/bin/prog", &data)?;
+    std::fs::write("target/tree_test/root/notes.txt", b"")?;
+    std::os::unix::fs::symlink("bin/prog", "target/tree_test/root/prog")?;
+    std::fs::write("target/tree_test/extra/filea", b"Hello, world!")?;
+    std::fs::write("target/tree_test/extra/docs/readme", b"read me\n")?;
+    std::fs::write(
+        "target/tree_test/fs.manifest",
+        "# fixtures\n\nextra/filea\n  extra/docs   /usr/share/docs\n",
+    )?;
+    let options = ImageOptions {
+        size: None,
+        inode_bitmap_blocks: 1,
+    };
+    let image = "target/tree_test/fs.img";
+    easy_fs_pack_tree(
+        "target/tree_test/root",
+        image,
+        Some("target/tree_test/fs.manifest"),
+        &options,
+    )?;
+    assert_eq!(fsck(open_image(image)?, false), vec![]);
+    let mut out = Vec::new();
+    easy_fs_ls(image, "/", &mut out)?;
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "       512 bin/\n       512 empty/\n         0 notes.txt\n         8 prog\n        13 filea\n       512 usr/\n"
+    );
+    let mut out = Vec::new();
+    easy_fs_cat(image, "/bin/prog", &mut out)?;
+    assert_eq!(out, data);
+    let mut out = Vec::new();
+    easy_fs_cat(image, "/usr/share/docs/readme", &mut out)?;
+    assert_eq!(out, b"read me\n");
+    let root_inode = open_root(image)?;
+    assert_eq!(
+        root_inode.find("prog").unwrap().read_link().unwrap(),
+        "bin/prog"
+    );
+
+    // a manifest entry clashing with the tree is refused
+    std::fs::write("target/tree_test/fs.manifest", "extra/filea /notes.txt\n")?;
+    let err = easy_fs_pack_tree(
+        "target/tree_test/root",
+        image,
+        Some("target/tree_test/fs.manifest"),
+        &options,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err
+        .to_string()
+        .starts_with("target/tree_test/fs.manifest:1: "));
+    std::fs::write(
+        "target/tree_test/fs.manifest",
+        "extra/filea /bin/prog/filea\n",
+    )?;
+    assert!(easy_fs_pack_tree(
+        "target/tree_test/root",
+        image,
+        Some("target/tree_test/fs.manifest"),
+        &options,
+    )
+    .is_err());
+    std::fs::write(
+        "target/tree_test/fs.manifest",
+        format!("extra/filea /{}\n", "z".repeat(NAME_LENGTH_LIMIT + 1)),
+    )?;
+    assert!(easy_fs_pack_tree(
+        "target/tree_test/root",
+        image,
+        Some("target/tree_test/fs.manifest"),
+        &options,
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs /dev/fuse and the right to mount"]
+fn efs_fuse_test() -> std::io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_image(8192);
+    EasyFileSystem::create(block_file.clone(), 8192, 1);
+    let efs = EasyFileSystem::open(block_file);
+    efs.lock().set_clock(fuse::host_clock);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let mnt = Path::new("target/mnt");
+    fs::create_dir_all(mnt)?;
+    let session = fuser::spawn_mount2(
+        fuse::EasyFuse::new(root_inode),
+        mnt,
+        &mount_options("target/fs.img"),
+    )?;
+    for i in 0..4 {
+        fs::create_dir(mnt.join(format!("dir{}", i)))?;
+    }
+    for i in 0..40 {
+        let data: Vec<u8> = (0..i * 700).map(|j| (i + j % 251) as u8).collect();
+        let path = mnt.join(format!("dir{}/file{}", i % 4, i));
+        fs::write(&path, &data)?;
+        assert_eq!(fs::read(&path)?, data);
+        let renamed = mnt.join(format!("dir{}/moved{}", (i + 1) % 4, i));
+        fs::rename(&path, &renamed)?;
+        assert!(!path.exists());
+        assert_eq!(fs::read(&renamed)?, data);
+        if i % 2 == 0 {
+            fs::remove_file(&renamed)?;
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .open(&renamed)?
+                .set_len(100)?;
+            assert_eq!(fs::metadata(&renamed)?.len(), 100);
+        }
+    }
+    assert_eq!(fs::read_dir(mnt.join("dir2"))?.count(), 10);
+    std::os::unix::fs::symlink("dir2/moved1", mnt.join("link"))?;
+    assert_eq!(fs::read_link(mnt.join("link"))?, Path::new("dir2/moved1"));
+    assert_eq!(fs::read(mnt.join("link"))?.len(), 100);
+    fs::hard_link(mnt.join("dir2/moved1"), mnt.join("hard"))?;
+    assert_eq!(fs::metadata(mnt.join("hard"))?.nlink(), 2);
+    assert_eq!(
+        fs::remove_dir(mnt.join("dir2")).unwrap_err().raw_os_error(),
+        Some(libc::ENOTEMPTY)
+    );
+    // an unlinked file is kept until it is closed
+    let mut file = fs::File::open(mnt.join("hard"))?;
+    fs::remove_file(mnt.join("hard"))?;
+    fs::remove_file(mnt.join("dir2/moved1"))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    assert_eq!(data.len(), 100);
+    drop(file);
+    session.join();
+    assert_eq!(fsck(open_image("target/fs.img")?, false), vec![]);
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! Plan the contents of a new image from host files before any of it is
+//! written, so that its size is known up front and bad input is refused
+//! before the image is touched.
+//!
+//! A manifest lists extra host paths, one per line, each optionally
+//! followed by where to put it in the image:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! fixtures/filea
+//! fixtures/data /tests/data
+//! ```
+//!
+//! Relative host paths are relative to the manifest. Without an image path
+//! an entry goes to the root under its host name. Directories are mirrored
+//! with everything below them.
+
+use easy_fs::{EasyFileSystem, Inode, NAME_LENGTH_LIMIT};
+use std::convert::TryFrom;
+use std::fs::{read_dir, read_link, symlink_metadata};
+use std::io::{Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub fn invalid_input(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+pub enum PackEntry {
+    File { host_path: PathBuf, size: u64 },
+    Symlink { target: String },
+    Dir(PackDir),
+}
+
+/// A directory of the image to be, its entries in the order they are created.
+#[derive(Default)]
+pub struct PackDir {
+    entries: Vec<(String, PackEntry)>,
+}
+
+impl PackDir {
+    /// Add `entry` as `name`, which must not be taken yet.
+    pub fn add(&mut self, name: &str, entry: PackEntry) -> std::io::Result<()> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(invalid_input(format!("{} is not a valid file name", name)));
+        }
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(invalid_input(format!(
+                "file name {} is {} bytes long, easy-fs names are limited to {} bytes",
+                name,
+                name.len(),
+                NAME_LENGTH_LIMIT
+            )));
+        }
+        if self.entries.iter().any(|(other, _)| other == name) {
+            return Err(invalid_input(format!("{} is packed twice", name)));
+        }
+        self.entries.push((String::from(name), entry));
+        Ok(())
+    }
+
+    /// Add `entry` at `path`, relative to this directory, creating the
+    /// directories leading to it.
+    pub fn add_path(&mut self, path: &str, entry: PackEntry) -> std::io::Result<()> {
+        let mut names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        let name = names
+            .pop()
+            .ok_or_else(|| invalid_input(format!("{} does not name a file", path)))?;
+        let mut dir = self;
+        for parent in names {
+            if !dir.entries.iter().any(|(other, _)| other == parent) {
+                dir.add(parent, PackEntry::Dir(PackDir::default()))?;
+            }
+            dir = match dir.entries.iter_mut().find(|(other, _)| other == parent) {
+                Some((_, PackEntry::Dir(sub_dir))) => sub_dir,
+                _ => {
+                    return Err(invalid_input(format!(
+                        "{} in {} is not a directory",
+                        parent, path
+                    )))
+                }
+            };
+        }
+        dir.add(name, entry)
+    }
+
+    /// Number of inodes taken by everything below this directory.
+    fn inodes(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|(_, entry)| match entry {
+                PackEntry::Dir(dir) => 1 + dir.inodes(),
+                _ => 1,
+            })
+            .sum()
+    }
+
+    /// Data blocks taken by this directory and everything below it.
+    fn data_blocks(&self) -> std::io::Result<u64> {
+        let size = EasyFileSystem::dir_size(self.entries.iter().map(|(name, _)| name.as_str()));
+        let mut blocks = EasyFileSystem::blocks_for_size(size) as u64;
+        for (name, entry) in self.entries.iter() {
+            let size = match entry {
+                PackEntry::File { size, .. } => *size,
+                PackEntry::Symlink { target } => target.len() as u64,
+                PackEntry::Dir(dir) => {
+                    blocks += dir.data_blocks()?;
+                    continue;
+                }
+            };
+            let size = u32::try_from(size)
+                .map_err(|_| invalid_input(format!("{} is too large for easy-fs", name)))?;
+            blocks += EasyFileSystem::blocks_for_size(size) as u64;
+        }
+        Ok(blocks)
+    }
+
+    /// Smallest image, in blocks, holding this directory as its root.
+    pub fn min_image_blocks(&self, inode_bitmap_blocks: u32) -> std::io::Result<u32> {
+        // the root takes an inode as well
+        let inodes = self.inodes() + 1;
+        if inodes > EasyFileSystem::max_inodes(inode_bitmap_blocks) as u64 {
+            return Err(invalid_input(format!(
+                "{} inodes need more than {} inode bitmap blocks",
+                inodes, inode_bitmap_blocks
+            )));
+        }
+        u32::try_from(self.data_blocks()?)
+            .map(|data_blocks| EasyFileSystem::min_total_blocks(inode_bitmap_blocks, data_blocks))
+            .map_err(|_| invalid_input(String::from("the files are too large for easy-fs")))
+    }
+
+    /// Create everything below this directory in `dir`.
+    pub fn write_to(&self, dir: &Arc<Inode>) -> std::io::Result<()> {
+        for (name, entry) in self.entries.iter() {
+            let inode = match entry {
+                PackEntry::File { .. } => dir.create(name),
+                PackEntry::Symlink { target } => dir.create_symlink(name, target),
+                PackEntry::Dir(_) => dir.create_dir(name),
+            }
+            .ok_or_else(|| invalid_input(format!("cannot create {} in easy-fs", name)))?;
+            match entry {
+                PackEntry::File { host_path, size } => {
+                    let mut data: Vec<u8> = Vec::new();
+                    std::fs::File::open(host_path)?.read_to_end(&mut data)?;
+                    // the image was sized for what was seen when planning
+                    if data.len() as u64 != *size {
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            format!("{} changed while packing", host_path.display()),
+                        ));
+                    }
+                    let written = inode.write_at(0, &data);
+                    if written != data.len() {
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            format!(
+                                "only {} of the {} bytes of {} fit in the image",
+                                written,
+                                data.len(),
+                                host_path.display()
+                            ),
+                        ));
+                    }
+                }
+                PackEntry::Symlink { .. } => {}
+                PackEntry::Dir(sub_dir) => sub_dir.write_to(&inode)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The entry mirroring the host file, symbolic link or directory `host_path`.
+pub fn host_entry(host_path: &Path) -> std::io::Result<PackEntry> {
+    let metadata = symlink_metadata(host_path)?;
+    let file_type = metadata.file_type();
+    if file_type.is_file() {
+        Ok(PackEntry::File {
+            host_path: host_path.to_path_buf(),
+            size: metadata.len(),
+        })
+    } else if file_type.is_symlink() {
+        let target = read_link(host_path)?;
+        let target = target
+            .to_str()
+            .filter(|target| !target.is_empty())
+            .ok_or_else(|| {
+                invalid_input(format!(
+                    "{} does not point at a valid path",
+                    host_path.display()
+                ))
+            })?;
+        Ok(PackEntry::Symlink {
+            target: String::from(target),
+        })
+    } else if file_type.is_dir() {
+        let mut names = Vec::new();
+        for dir_entry in read_dir(host_path)? {
+            names.push(dir_entry?.file_name());
+        }
+        // pack in a fixed order, so that the same tree gives the same image
+        names.sort();
+        let mut dir = PackDir::default();
+        for name in names {
+            let entry = host_entry(&host_path.join(&name))?;
+            let name = name
+                .to_str()
+                .ok_or_else(|| invalid_input(format!("{:?} is not valid UTF-8", name)))?;
+            dir.add(name, entry)?;
+        }
+        Ok(PackEntry::Dir(dir))
+    } else {
+        Err(invalid_input(format!(
+            "{} is not a file, a directory or a symbolic link",
+            host_path.display()
+        )))
+    }
+}
+
+/// Add the host paths listed in the manifest `manifest_path` to `root`.
+pub fn add_manifest(root: &mut PackDir, manifest_path: &str) -> std::io::Result<()> {
+    let manifest = std::fs::read_to_string(manifest_path)?;
+    let base = Path::new(manifest_path).parent().unwrap_or(Path::new(""));
+    for (line_no, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let host_path = base.join(fields.next().unwrap());
+        let image_path = match (fields.next(), fields.next()) {
+            (Some(image_path), None) => String::from(image_path),
+            (None, _) => host_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(String::from)
+                .ok_or_else(|| {
+                    invalid_input(format!(
+                        "{}:{}: {} has no file name",
+                        manifest_path,
+                        line_no + 1,
+                        host_path.display()
+                    ))
+                })?,
+            _ => {
+                return Err(invalid_input(format!(
+                    "{}:{}: expected a host path and an optional image path",
+                    manifest_path,
+                    line_no + 1
+                )))
+            }
+        };
+        host_entry(&host_path)
+            .and_then(|entry| root.add_path(&image_path, entry))
+            .map_err(|err| {
+                Error::new(
+                    err.kind(),
+                    format!("{}:{}: {}", manifest_path, line_no + 1, err),
+                )
+            })?;
+    }
+    Ok(())
+}
@@ -1,11 +1,18 @@
-use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
+use super::{BlockDevice, Journal, BLOCK_SZ};
+use crate::journal::JOURNAL_CAPACITY;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::hash::{BuildHasherDefault, Hasher};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hashbrown::HashMap;
 use lazy_static::*;
 use spin::Mutex;
 
+/// Modified blocks not written back yet, whether cached or pending.
+static DIRTY_BLOCKS: AtomicUsize = AtomicUsize::new(0);
+
 pub struct BlockCache {
     cache: Vec<u8>,
     block_id: usize,
@@ -47,7 +54,10 @@ impl BlockCache {
     {
         let type_size = core::mem::size_of::<T>();
         assert!(offset + type_size <= BLOCK_SZ);
-        self.modified = true;
+        if !self.modified {
+            self.modified = true;
+            DIRTY_BLOCKS.fetch_add(1, Ordering::Relaxed);
+        }
         let addr = self.addr_of_offset(offset);
         unsafe { &mut *(addr as *mut T) }
     }
@@ -61,10 +71,19 @@ impl BlockCache {
     }
 
     pub fn sync(&mut self) {
-        if self.modified {
-            self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
+        if let Some(data) = self.take_modified() {
+            self.block_device.write_block(self.block_id, &data);
+        }
+    }
+
+    /// Mark the block clean, returning its contents if it was modified.
+    fn take_modified(&mut self) -> Option<Vec<u8>> {
+        if !self.modified {
+            return None;
         }
+        self.modified = false;
+        DIRTY_BLOCKS.fetch_sub(1, Ordering::Relaxed);
+        Some(self.cache.clone())
     }
 }
 
@@ -74,55 +93,157 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 16;
+/// Blocks kept in memory until `block_cache_set_capacity` is called.
+const DEFAULT_CAPACITY: usize = 16;
+
+/// Most blocks a single transaction, from one `block_cache_end_transaction`
+/// to the next, may modify. Finished transactions are left in the cache and
+/// committed together once another one might not fit into the journal.
+const MAX_TRANSACTION_BLOCKS: usize = 32;
+
+/// Fibonacci hashing of block ids, which are all the cache is keyed by.
+#[derive(Default)]
+struct BlockIdHasher(u64);
+
+impl Hasher for BlockIdHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(self.0.rotate_left(8) ^ *byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+type BlockMap<V> = HashMap<usize, V, BuildHasherDefault<BlockIdHasher>>;
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// Blocks the cache should not grow beyond. It does when all of them are
+    /// in use, and shrinks back as they are released.
+    capacity: usize,
+    /// Cached blocks with the tick of their last use.
+    blocks: BlockMap<(u64, Arc<Mutex<BlockCache>>)>,
+    /// Cached block ids by the tick of their last use, least recent first.
+    lru: BTreeMap<u64, usize>,
+    tick: u64,
+    /// Modified blocks go home through the journal once it is set.
+    journal: Option<Journal>,
+    /// Modified blocks evicted before the next commit, kept back from disk.
+    pending: BlockMap<Vec<u8>>,
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
-            queue: VecDeque::new(),
+            capacity,
+            blocks: BlockMap::default(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            journal: None,
+            pending: BlockMap::default(),
         }
     }
 
+    /// Write back all modified blocks. With a journal they are committed
+    /// as one transaction.
+    pub fn sync_all(&mut self) {
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => {
+                for (_, cache) in self.blocks.values() {
+                    cache.lock().sync();
+                }
+                return;
+            }
+        };
+        let mut blocks: Vec<(usize, Vec<u8>)> = self.pending.drain().collect();
+        DIRTY_BLOCKS.fetch_sub(blocks.len(), Ordering::Relaxed);
+        for (_, cache) in self.blocks.values() {
+            let mut cache = cache.lock();
+            if let Some(data) = cache.take_modified() {
+                blocks.push((cache.block_id, data));
+            }
+        }
+        if !blocks.is_empty() {
+            // keep the journal in the order of the disk
+            blocks.sort_unstable_by_key(|(block_id, _)| *block_id);
+            journal.commit(&blocks);
+        }
+    }
+
+    /// Evict the least recently used block nobody else holds.
+    /// Return `false` if all of them are in use.
+    fn evict(&mut self) -> bool {
+        let victim = self
+            .lru
+            .iter()
+            .map(|(&tick, &block_id)| (tick, block_id))
+            .find(|(_, block_id)| Arc::strong_count(&self.blocks[block_id].1) == 1);
+        let (tick, block_id) = match victim {
+            Some(victim) => victim,
+            None => return false,
+        };
+        self.lru.remove(&tick);
+        let (_, victim) = self.blocks.remove(&block_id).unwrap();
+        if self.journal.is_some() {
+            // an uncommitted block must not reach its home yet
+            let mut victim = victim.lock();
+            if victim.modified {
+                // still dirty, only no longer cached
+                victim.modified = false;
+                self.pending.insert(block_id, victim.cache.clone());
+            }
+        }
+        true
+    }
+
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
-            }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((last_use, cache)) = self.blocks.get_mut(&block_id) {
+            self.lru.remove(last_use);
+            self.lru.insert(tick, block_id);
+            *last_use = tick;
+            return Arc::clone(cache);
+        }
+        while self.blocks.len() >= self.capacity && self.evict() {}
+        // load block into mem, from the pending blocks if it was evicted dirty
+        let block_cache = match self.pending.remove(&block_id) {
+            Some(cache) => Arc::new(Mutex::new(BlockCache {
+                cache,
+                block_id,
+                block_device: Arc::clone(&block_device),
+                modified: true,
+            })),
+            None => Arc::new(Mutex::new(BlockCache::new(
                 block_id,
                 Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
-        }
+            ))),
+        };
+        self.lru.insert(tick, block_id);
+        self.blocks
+            .insert(block_id, (tick, Arc::clone(&block_cache)));
+        block_cache
     }
 }
 
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
-        Mutex::new(BlockCacheManager::new());
+        Mutex::new(BlockCacheManager::new(DEFAULT_CAPACITY));
 }
 
 pub fn get_block_cache(
@@ -134,9 +255,47 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 
+/// Write back all modified blocks, committing them as one transaction if
+/// there is a journal. Everything done before is durable once it returns.
 pub fn block_cache_sync_all() {
+    BLOCK_CACHE_MANAGER.lock().sync_all();
+}
+
+/// Whether `block_id` was modified since the last commit.
+pub fn block_cache_is_dirty(block_id: usize) -> bool {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    manager.pending.contains_key(&block_id)
+        || manager
+            .blocks
+            .get(&block_id)
+            .map_or(false, |(_, cache)| cache.lock().modified)
+}
+
+/// Mark the end of a transaction, which each `Inode` operation finishes
+/// with. Its blocks are written back with the next commit, which only
+/// happens here if the journal could not take another transaction.
+pub fn block_cache_end_transaction() {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    if manager.journal.is_none()
+        || DIRTY_BLOCKS.load(Ordering::Relaxed) + MAX_TRANSACTION_BLOCKS > JOURNAL_CAPACITY
+    {
+        manager.sync_all();
     }
 }
+
+/// Keep at most `capacity` blocks in memory while they are not in use.
+pub fn block_cache_set_capacity(capacity: usize) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.capacity = capacity.max(1);
+    while manager.blocks.len() > manager.capacity && manager.evict() {}
+}
+
+/// Write back and forget all cached blocks, then send later writes through
+/// `journal`. Called when a filesystem is created or opened.
+pub fn block_cache_reset(journal: Option<Journal>) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.sync_all();
+    manager.blocks.clear();
+    manager.lru.clear();
+    manager.journal = journal;
+}
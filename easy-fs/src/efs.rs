@@ -1,9 +1,11 @@
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache_reset, block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DirEntry,
+    DiskInode, DiskInodeType, Inode, Journal, SuperBlock, JOURNAL_BLOCKS,
 };
 use crate::BLOCK_SZ;
-use alloc::sync::Arc;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
 use spin::Mutex;
 
 pub struct EasyFileSystem {
@@ -12,35 +14,101 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    clock: fn() -> u64,
+    /// The `Inode` of every inode id in use, so that all users share one.
+    inodes: BTreeMap<u32, Weak<Inode>>,
+    /// Size of `inodes` at which the entries of dropped inodes are purged.
+    purge_at: usize,
+}
+
+/// Entries of `EasyFileSystem::inodes` left before dropped ones are purged.
+const MIN_PURGE_AT: usize = 64;
+
+/// The clock used until [`EasyFileSystem::set_clock`] is called.
+fn no_clock() -> u64 {
+    0
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Blocks of the inode area for `inode_bitmap_blocks` blocks of inode bitmap.
+fn inode_area_blocks(inode_bitmap_blocks: u32) -> u32 {
+    let inode_num = inode_bitmap_blocks as usize * BLOCK_SZ * 8;
+    ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32
+}
+
+/// Split the blocks after the inode area into (data bitmap, data area).
+fn data_layout(data_total_blocks: u32) -> (u32, u32) {
+    let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
+    (data_bitmap_blocks, data_total_blocks - data_bitmap_blocks)
+}
+
 impl EasyFileSystem {
+    /// Number of inodes, the root included, that `inode_bitmap_blocks` blocks
+    /// of inode bitmap can hold.
+    pub fn max_inodes(inode_bitmap_blocks: u32) -> u32 {
+        inode_bitmap_blocks * BLOCK_SZ as u32 * 8
+    }
+
+    /// Smallest `total_blocks` for which `create` leaves at least
+    /// `data_blocks` blocks in the data area.
+    pub fn min_total_blocks(inode_bitmap_blocks: u32, data_blocks: u32) -> u32 {
+        let mut data_total_blocks = data_blocks + (data_blocks + 4095) / 4096;
+        while data_layout(data_total_blocks).1 < data_blocks {
+            data_total_blocks += 1;
+        }
+        1 + JOURNAL_BLOCKS
+            + inode_bitmap_blocks
+            + inode_area_blocks(inode_bitmap_blocks)
+            + data_total_blocks
+    }
+
+    /// Data blocks, index blocks included, taken by a file of `size` bytes.
+    pub fn blocks_for_size(size: u32) -> u32 {
+        DiskInode::total_blocks(size)
+    }
+
+    /// Size of a new directory after `names` are added to it in order.
+    pub fn dir_size<'a>(names: impl IntoIterator<Item = &'a str>) -> u32 {
+        // the slack at the end of each block, the first one holding "." and ".."
+        let mut slack = vec![BLOCK_SZ - DirEntry::size_for(1) - DirEntry::size_for(2)];
+        for name in names {
+            let needed = DirEntry::size_for(name.len());
+            match slack.iter_mut().find(|slack| **slack >= needed) {
+                Some(slack) => *slack -= needed,
+                None => slack.push(BLOCK_SZ - needed),
+            }
+        }
+        (slack.len() * BLOCK_SZ) as u32
+    }
+
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
     ) -> Arc<Mutex<Self>> {
+        // nothing is journaled until the filesystem is complete
+        block_cache_reset(None);
         // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
-        let inode_num = inode_bitmap.maximum();
-        let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
+        let journal_blocks = JOURNAL_BLOCKS;
+        let inode_bitmap = Bitmap::new(1 + journal_blocks as usize, inode_bitmap_blocks as usize);
+        let inode_area_blocks = inode_area_blocks(inode_bitmap_blocks);
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
-        let data_area_blocks = data_total_blocks - data_bitmap_blocks;
+        let data_total_blocks = total_blocks - 1 - journal_blocks - inode_total_blocks;
+        let (data_bitmap_blocks, data_area_blocks) = data_layout(data_total_blocks);
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            (1 + journal_blocks + inode_total_blocks) as usize,
             data_bitmap_blocks as usize,
         );
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_area_start_block: 1 + journal_blocks + inode_bitmap_blocks,
+            data_area_start_block: 1 + journal_blocks + inode_total_blocks + data_bitmap_blocks,
+            clock: no_clock,
+            inodes: BTreeMap::new(),
+            purge_at: MIN_PURGE_AT,
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -58,6 +126,7 @@ impl EasyFileSystem {
             |super_block: &mut SuperBlock| {
                 super_block.initialize(
                     total_blocks,
+                    journal_blocks,
                     inode_bitmap_blocks,
                     inode_area_blocks,
                     data_bitmap_blocks,
@@ -73,39 +142,96 @@ impl EasyFileSystem {
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
                 disk_inode.initialize(DiskInodeType::Directory);
+                // the parent of "/" is itself
+                efs.init_dir_entries(disk_inode, 0, 0);
             });
         block_cache_sync_all();
+        block_cache_reset(Some(Journal::new(1, block_device)));
         Arc::new(Mutex::new(efs))
     }
 
+    /// Open the filesystem on `block_device`, first finishing a transaction
+    /// which was interrupted after its commit.
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+        block_cache_reset(None);
+        let journal = Journal::new(1, Arc::clone(&block_device));
+        journal.replay();
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
-                assert!(super_block.is_valid(), "Error loading EFS!");
+        let efs = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
+                assert!(
+                    super_block.is_valid(),
+                    "Error loading EFS: bad magic, the image has to be rebuilt!"
+                );
+                let journal_blocks = super_block.journal_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
                 let efs = Self {
                     block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
+                    inode_bitmap: Bitmap::new(
+                        (1 + journal_blocks) as usize,
+                        super_block.inode_bitmap_blocks as usize,
+                    ),
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (1 + journal_blocks + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    inode_area_start_block: 1 + journal_blocks + super_block.inode_bitmap_blocks,
+                    data_area_start_block: 1
+                        + journal_blocks
+                        + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                    clock: no_clock,
+                    inodes: BTreeMap::new(),
+                    purge_at: MIN_PURGE_AT,
                 };
                 Arc::new(Mutex::new(efs))
-            })
+            },
+        );
+        block_cache_reset(Some(journal));
+        efs
+    }
+
+    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Arc<Inode> {
+        efs.lock().get_inode(efs, 0)
     }
 
-    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
-        let block_device = Arc::clone(&efs.lock().block_device);
-        // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
-        // release efs lock
-        Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+    /// Return the `Inode` of `inode_id`, the same one as long as anybody
+    /// holds it. `efs` is the filesystem this is locked from.
+    pub fn get_inode(&mut self, efs: &Arc<Mutex<Self>>, inode_id: u32) -> Arc<Inode> {
+        if let Some(inode) = self.open_inode(inode_id) {
+            return inode;
+        }
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        let inode = Arc::new(Inode::new(
+            inode_id,
+            block_id,
+            block_offset,
+            Arc::clone(efs),
+            Arc::clone(&self.block_device),
+        ));
+        if self.inodes.len() >= self.purge_at {
+            self.inodes.retain(|_, inode| inode.strong_count() > 0);
+            self.purge_at = MIN_PURGE_AT.max(self.inodes.len() * 2);
+        }
+        self.inodes.insert(inode_id, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Return the `Inode` of `inode_id` if anybody holds it.
+    pub fn open_inode(&self, inode_id: u32) -> Option<Arc<Inode>> {
+        self.inodes.get(&inode_id).and_then(Weak::upgrade)
+    }
+
+    /// Use `clock` to timestamp inodes from now on.
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    /// Current time of the filesystem clock.
+    pub fn now(&self) -> u64 {
+        (self.clock)()
     }
 
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
@@ -126,6 +252,25 @@ impl EasyFileSystem {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
 
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
+    }
+
+    /// Fill a freshly initialized directory with its "." and ".." entries,
+    /// which take up its first block.
+    pub fn init_dir_entries(&mut self, disk_inode: &mut DiskInode, inode_id: u32, parent_id: u32) {
+        let block_device = Arc::clone(&self.block_device);
+        disk_inode.increase_size(BLOCK_SZ as u32);
+        disk_inode.alloc_blocks(0, BLOCK_SZ, || self.alloc_data(), &block_device);
+        let mut block = [0u8; BLOCK_SZ];
+        let dot = DirEntry::new(".", inode_id, DirEntry::size_for(1));
+        dot.write_to(&mut block);
+        DirEntry::new("..", parent_id, BLOCK_SZ - dot.rec_len())
+            .write_to(&mut block[dot.rec_len()..]);
+        disk_inode.write_at(0, &block, &self.block_device);
+    }
+
     /// Return a block ID not ID in the data area.
     pub fn alloc_data(&mut self) -> u32 {
         self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
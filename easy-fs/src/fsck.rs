@@ -0,0 +1,399 @@
+//! Consistency check of an easy-fs image, in the spirit of `e2fsck`.
+//!
+//! Starting from the root directory, every reachable `DiskInode` is walked
+//! down its direct/indirect1/indirect2 tree, and the blocks and directory
+//! entries found are compared with both `Bitmap`s and the link counts.
+
+use super::{
+    block_cache_reset, block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode,
+    EasyFileSystem, SuperBlock, BLOCK_SZ, DIRENT_HEADER_SZ,
+};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+/// An inconsistency found by [`fsck`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsckProblem {
+    /// Block 0 does not hold the `SuperBlock` of an easy-fs image.
+    BadSuperBlock,
+    /// Allocated in the inode bitmap but not reachable from the root.
+    LeakedInode(u32),
+    /// Allocated in the data bitmap but owned by no inode.
+    LeakedBlock(u32),
+    /// Owned by an inode but free in the data bitmap.
+    UnallocatedBlock { block_id: u32, inode_id: u32 },
+    /// Owned by two inodes, or twice by the same one.
+    DuplicateBlock {
+        block_id: u32,
+        inode_ids: (u32, u32),
+    },
+    /// A directory entry naming an inode which is not allocated.
+    DanglingEntry {
+        dir_id: u32,
+        name: String,
+        inode_id: u32,
+    },
+    /// The block tree points outside the data area, so only the first
+    /// `valid_size` bytes can be read. Holes are fine.
+    SizeMismatch {
+        inode_id: u32,
+        size: u32,
+        valid_size: u32,
+    },
+    /// `nlink` differs from the number of entries naming the inode.
+    WrongLinkCount {
+        inode_id: u32,
+        nlink: u32,
+        links: u32,
+    },
+    /// A directory block whose entries do not add up to the block.
+    CorruptDirectory { inode_id: u32, offset: usize },
+}
+
+impl FsckProblem {
+    /// Whether [`fsck`] fixes this problem when asked to repair.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(
+            self,
+            Self::BadSuperBlock | Self::DuplicateBlock { .. } | Self::CorruptDirectory { .. }
+        )
+    }
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::BadSuperBlock => write!(f, "bad super block"),
+            Self::LeakedInode(inode_id) => write!(f, "inode {} is not reachable", inode_id),
+            Self::LeakedBlock(block_id) => {
+                write!(f, "block {} is allocated but not used", block_id)
+            }
+            Self::UnallocatedBlock { block_id, inode_id } => write!(
+                f,
+                "block {} of inode {} is not allocated",
+                block_id, inode_id
+            ),
+            Self::DuplicateBlock {
+                block_id,
+                inode_ids: (first, second),
+            } => write!(
+                f,
+                "block {} is claimed by inodes {} and {}",
+                block_id, first, second
+            ),
+            Self::DanglingEntry {
+                dir_id,
+                name,
+                inode_id,
+            } => write!(
+                f,
+                "entry {:?} of directory {} refers to free inode {}",
+                name, dir_id, inode_id
+            ),
+            Self::SizeMismatch {
+                inode_id,
+                size,
+                valid_size,
+            } => write!(
+                f,
+                "inode {} has size {} but blocks for only {} bytes",
+                inode_id, size, valid_size
+            ),
+            Self::WrongLinkCount {
+                inode_id,
+                nlink,
+                links,
+            } => write!(
+                f,
+                "inode {} has nlink {} but {} links",
+                inode_id, nlink, links
+            ),
+            Self::CorruptDirectory { inode_id, offset } => write!(
+                f,
+                "directory {} has corrupted entries at offset {}",
+                inode_id, offset
+            ),
+        }
+    }
+}
+
+/// Check the easy-fs image on `block_device` and return the problems found.
+/// With `repair`, those which are repairable are fixed on the way.
+///
+/// As in [`EasyFileSystem::open`], a committed transaction left in the
+/// journal is replayed first.
+pub fn fsck(block_device: Arc<dyn BlockDevice>, repair: bool) -> Vec<FsckProblem> {
+    // the cache may still hold blocks of another image
+    block_cache_reset(None);
+    let layout =
+        get_block_cache(0, Arc::clone(&block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                let blocks = 1
+                    + super_block.journal_blocks as u64
+                    + super_block.inode_bitmap_blocks as u64
+                    + super_block.inode_area_blocks as u64
+                    + super_block.data_bitmap_blocks as u64
+                    + super_block.data_area_blocks as u64;
+                if super_block.is_valid() && blocks == super_block.total_blocks as u64 {
+                    Some(super_block.data_area_blocks)
+                } else {
+                    None
+                }
+            });
+    let data_area_blocks = match layout {
+        Some(data_area_blocks) => data_area_blocks,
+        None => return vec![FsckProblem::BadSuperBlock],
+    };
+    let efs = EasyFileSystem::open(Arc::clone(&block_device));
+    let mut fs = efs.lock();
+    let mut checker = Checker {
+        block_device,
+        problems: Vec::new(),
+        repair,
+        data_area: fs.get_data_block_id(0)..fs.get_data_block_id(data_area_blocks),
+        owners: BTreeMap::new(),
+        links: vec![0; fs.inode_bitmap.maximum()],
+        reached: vec![false; fs.inode_bitmap.maximum()],
+    };
+    checker.walk(&fs);
+    checker.check_inodes(&mut fs);
+    checker.check_blocks(&mut fs, data_area_blocks);
+    checker.problems
+}
+
+struct Checker {
+    block_device: Arc<dyn BlockDevice>,
+    problems: Vec<FsckProblem>,
+    repair: bool,
+    data_area: core::ops::Range<u32>,
+    /// Owner of every block used by a reachable inode.
+    owners: BTreeMap<u32, u32>,
+    /// Number of directory entries naming each inode.
+    links: Vec<u32>,
+    reached: Vec<bool>,
+}
+
+impl Checker {
+    fn read_disk_inode<V>(
+        &self,
+        fs: &EasyFileSystem,
+        inode_id: u32,
+        f: impl FnOnce(&DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, f)
+    }
+
+    fn modify_disk_inode<V>(
+        &self,
+        fs: &EasyFileSystem,
+        inode_id: u32,
+        f: impl FnOnce(&mut DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let ret = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f);
+        block_cache_sync_all();
+        ret
+    }
+
+    /// Visit every inode reachable from the root, breadth first.
+    fn walk(&mut self, fs: &EasyFileSystem) {
+        let mut queue = VecDeque::new();
+        queue.push_back(0);
+        self.reached[0] = true;
+        while let Some(inode_id) = queue.pop_front() {
+            let size = self.check_blocks_of(fs, inode_id);
+            if self.read_disk_inode(fs, inode_id, |disk_inode| disk_inode.is_dir()) {
+                self.check_dirents_of(fs, inode_id, size, &mut queue);
+            }
+        }
+    }
+
+    /// Check the size of `inode_id` against its block tree and claim the
+    /// blocks of the tree. Return how many bytes can be read.
+    fn check_blocks_of(&mut self, fs: &EasyFileSystem, inode_id: u32) -> u32 {
+        let data_area = self.data_area.clone();
+        let valid = |block_id: u32| data_area.contains(&block_id);
+        let (mut size, data_blocks) = self.read_disk_inode(fs, inode_id, |disk_inode| {
+            (disk_inode.size, disk_inode.data_blocks())
+        });
+        let (mut blocks, reached) = self.read_disk_inode(fs, inode_id, |disk_inode| {
+            disk_inode.collect_blocks(data_blocks, &self.block_device, valid)
+        });
+        if reached < data_blocks {
+            let valid_size = reached * BLOCK_SZ as u32;
+            self.problems.push(FsckProblem::SizeMismatch {
+                inode_id,
+                size,
+                valid_size,
+            });
+            // index blocks past the valid ones are no longer needed
+            blocks = self.read_disk_inode(fs, inode_id, |disk_inode| {
+                disk_inode
+                    .collect_blocks(reached, &self.block_device, valid)
+                    .0
+            });
+            if self.repair {
+                self.modify_disk_inode(fs, inode_id, |disk_inode| {
+                    disk_inode.cut_blocks(reached, &self.block_device)
+                });
+            }
+            size = valid_size;
+        }
+        for block_id in blocks {
+            if let Some(&first) = self.owners.get(&block_id) {
+                self.problems.push(FsckProblem::DuplicateBlock {
+                    block_id,
+                    inode_ids: (first, inode_id),
+                });
+            } else {
+                self.owners.insert(block_id, inode_id);
+            }
+        }
+        size
+    }
+
+    /// Count the links made by the entries of directory `dir_id` and queue
+    /// the inodes they name for a visit.
+    fn check_dirents_of(
+        &mut self,
+        fs: &EasyFileSystem,
+        dir_id: u32,
+        size: u32,
+        queue: &mut VecDeque<u32>,
+    ) {
+        let mut block = [0u8; BLOCK_SZ];
+        for block_start in (0..size as usize).step_by(BLOCK_SZ) {
+            self.read_disk_inode(fs, dir_id, |disk_inode| {
+                disk_inode.read_at(block_start, &mut block, &self.block_device)
+            });
+            let mut modified = false;
+            let mut pos = 0;
+            while pos < BLOCK_SZ {
+                let dirent = match Self::parse_dirent(&block[pos..]) {
+                    Some(dirent) => dirent,
+                    None => {
+                        self.problems.push(FsckProblem::CorruptDirectory {
+                            inode_id: dir_id,
+                            offset: block_start + pos,
+                        });
+                        break;
+                    }
+                };
+                let inode_id = dirent.inode_number();
+                if !dirent.is_empty() {
+                    if (inode_id as usize) < self.links.len()
+                        && fs
+                            .inode_bitmap
+                            .is_allocated(&self.block_device, inode_id as usize)
+                    {
+                        self.links[inode_id as usize] += 1;
+                        if !self.reached[inode_id as usize] {
+                            self.reached[inode_id as usize] = true;
+                            queue.push_back(inode_id);
+                        }
+                    } else {
+                        self.problems.push(FsckProblem::DanglingEntry {
+                            dir_id,
+                            name: String::from(dirent.name()),
+                            inode_id,
+                        });
+                        if self.repair {
+                            DirEntry::remove(&mut block, pos);
+                            modified = true;
+                        }
+                    }
+                }
+                pos += dirent.rec_len();
+            }
+            if modified {
+                self.modify_disk_inode(fs, dir_id, |disk_inode| {
+                    disk_inode.write_at(block_start, &block, &self.block_device)
+                });
+            }
+        }
+    }
+
+    /// Parse the entry at the start of `bytes`, unless it is malformed.
+    fn parse_dirent(bytes: &[u8]) -> Option<DirEntry> {
+        if bytes.len() < DIRENT_HEADER_SZ {
+            return None;
+        }
+        let rec_len = u16::from_le_bytes([bytes[4], bytes[5]]) as usize;
+        let name_len = bytes[6] as usize;
+        if rec_len % 4 != 0 || rec_len < DirEntry::size_for(name_len) || rec_len > bytes.len() {
+            return None;
+        }
+        core::str::from_utf8(&bytes[DIRENT_HEADER_SZ..DIRENT_HEADER_SZ + name_len]).ok()?;
+        Some(DirEntry::read_from(bytes))
+    }
+
+    /// Compare the inode bitmap and the link counts with the walk.
+    fn check_inodes(&mut self, fs: &mut EasyFileSystem) {
+        for inode_id in 0..self.links.len() as u32 {
+            if !self.reached[inode_id as usize] {
+                if fs
+                    .inode_bitmap
+                    .is_allocated(&self.block_device, inode_id as usize)
+                {
+                    // its blocks are not claimed, so they are released below
+                    self.problems.push(FsckProblem::LeakedInode(inode_id));
+                    if self.repair {
+                        fs.dealloc_inode(inode_id);
+                        block_cache_sync_all();
+                    }
+                }
+                continue;
+            }
+            let links = self.links[inode_id as usize];
+            let nlink = self.read_disk_inode(fs, inode_id, |disk_inode| disk_inode.nlink);
+            if nlink != links {
+                self.problems.push(FsckProblem::WrongLinkCount {
+                    inode_id,
+                    nlink,
+                    links,
+                });
+                if self.repair {
+                    self.modify_disk_inode(fs, inode_id, |disk_inode| disk_inode.nlink = links);
+                }
+            }
+        }
+    }
+
+    /// Compare the data bitmap with the blocks claimed during the walk.
+    fn check_blocks(&mut self, fs: &mut EasyFileSystem, data_area_blocks: u32) {
+        for bit in 0..data_area_blocks {
+            let block_id = fs.get_data_block_id(bit);
+            let allocated = fs
+                .data_bitmap
+                .is_allocated(&self.block_device, bit as usize);
+            match (allocated, self.owners.get(&block_id)) {
+                (true, None) => {
+                    self.problems.push(FsckProblem::LeakedBlock(block_id));
+                    if self.repair {
+                        fs.dealloc_data(block_id);
+                        block_cache_sync_all();
+                    }
+                }
+                (false, Some(&inode_id)) => {
+                    self.problems
+                        .push(FsckProblem::UnallocatedBlock { block_id, inode_id });
+                    if self.repair {
+                        fs.data_bitmap.alloc_bit(&self.block_device, bit as usize);
+                        block_cache_sync_all();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! A write-ahead journal of whole blocks, kept right after the `SuperBlock`.
+//!
+//! A transaction is committed by first copying the new contents of its
+//! blocks into the journal and then writing the journal header, which lists
+//! their home locations. Only after that are the blocks written home and
+//! the header cleared again. A header found when the filesystem is opened
+//! belongs to a committed transaction which may not have reached home
+//! completely, so its blocks are copied home once more.
+
+use super::{BlockDevice, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const JOURNAL_MAGIC: u32 = 0x6a726e6c;
+/// Most blocks a single transaction may change.
+pub const JOURNAL_CAPACITY: usize = 64;
+/// Blocks taken by the journal: the header and one copy per block.
+pub const JOURNAL_BLOCKS: u32 = 1 + JOURNAL_CAPACITY as u32;
+
+/// The journal header holds the magic, the number of blocks and their
+/// home block ids, each as a little-endian `u32`.
+type JournalHeader = [u8; BLOCK_SZ];
+
+pub struct Journal {
+    start_block: usize,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl Journal {
+    pub fn new(start_block: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            start_block,
+            block_device,
+        }
+    }
+
+    /// Write `blocks`, given as (block id, contents), as one atomic update.
+    pub fn commit(&self, blocks: &[(usize, Vec<u8>)]) {
+        assert!(
+            blocks.len() <= JOURNAL_CAPACITY,
+            "a transaction of {} blocks does not fit into the journal",
+            blocks.len()
+        );
+        for (i, (_, data)) in blocks.iter().enumerate() {
+            self.block_device
+                .write_block(self.start_block + 1 + i, data);
+        }
+        let mut header: JournalHeader = [0u8; BLOCK_SZ];
+        header[0..4].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for (i, (block_id, _)) in blocks.iter().enumerate() {
+            header[8 + i * 4..12 + i * 4].copy_from_slice(&(*block_id as u32).to_le_bytes());
+        }
+        // the transaction is committed once the header is on disk
+        self.block_device.write_block(self.start_block, &header);
+        for (block_id, data) in blocks.iter() {
+            self.block_device.write_block(*block_id, data);
+        }
+        self.block_device
+            .write_block(self.start_block, &[0u8; BLOCK_SZ]);
+    }
+
+    /// Finish writing a committed transaction home.
+    /// Return the number of blocks replayed.
+    ///
+    /// The block cache must not hold any of the blocks involved.
+    pub fn replay(&self) -> usize {
+        let mut header: JournalHeader = [0u8; BLOCK_SZ];
+        self.block_device.read_block(self.start_block, &mut header);
+        let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        if word(0) != JOURNAL_MAGIC {
+            return 0;
+        }
+        let count = word(1) as usize;
+        assert!(count <= JOURNAL_CAPACITY, "corrupted journal header");
+        let mut data = [0u8; BLOCK_SZ];
+        for i in 0..count {
+            self.block_device
+                .read_block(self.start_block + 1 + i, &mut data);
+            self.block_device.write_block(word(2 + i) as usize, &data);
+        }
+        self.block_device
+            .write_block(self.start_block, &[0u8; BLOCK_SZ]);
+        count
+    }
+}
@@ -3,20 +3,23 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
 
-const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
+const EFS_MAGIC: u32 = 0x3b800005;
+const INODE_DIRECT_COUNT: usize = 23;
+pub const NAME_LENGTH_LIMIT: usize = 255;
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
 const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-#[allow(unused)]
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+/// The most an inode can map, a bit over 8 MiB.
+pub const MAX_FILE_SIZE: usize = INDIRECT2_BOUND * BLOCK_SZ;
 
 #[repr(C)]
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
+    /// The journal starts right after the super block.
+    pub journal_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
@@ -27,6 +30,7 @@ impl Debug for SuperBlock {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         f.debug_struct("SuperBlock")
             .field("total_blocks", &self.total_blocks)
+            .field("journal_blocks", &self.journal_blocks)
             .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
@@ -39,6 +43,7 @@ impl SuperBlock {
     pub fn initialize(
         &mut self,
         total_blocks: u32,
+        journal_blocks: u32,
         inode_bitmap_blocks: u32,
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
@@ -47,6 +52,7 @@ impl SuperBlock {
         *self = Self {
             magic: EFS_MAGIC,
             total_blocks,
+            journal_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
@@ -58,10 +64,12 @@ impl SuperBlock {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
+    /// The data blocks hold the target path.
+    Symlink,
 }
 
 type IndirectBlock = [u32; BLOCK_SZ / 4];
@@ -73,17 +81,40 @@ pub struct DiskInode {
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    /// Number of directory entries referring to this inode.
+    pub nlink: u32,
     type_: DiskInodeType,
+    /// Last modification of the contents, in ms of the filesystem clock.
+    pub mtime: u64,
+    /// Last change of the contents or the metadata, in ms of the filesystem clock.
+    pub ctime: u64,
 }
 
 impl DiskInode {
     /// indirect1 and indirect2 block are allocated only when they are needed.
+    ///
+    /// A directory starts with two links: its entry in the parent and its own ".".
     pub fn initialize(&mut self, type_: DiskInodeType) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.nlink = if type_ == DiskInodeType::Directory {
+            2
+        } else {
+            1
+        };
         self.type_ = type_;
+        self.mtime = 0;
+        self.ctime = 0;
+    }
+    pub fn type_(&self) -> DiskInodeType {
+        self.type_
+    }
+    /// Record a change of the contents at time `now`.
+    pub fn touch(&mut self, now: u64) {
+        self.mtime = now;
+        self.ctime = now;
     }
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
@@ -92,12 +123,16 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::Symlink
+    }
     /// Return block number correspond to size.
     pub fn data_blocks(&self) -> u32 {
         Self::_data_blocks(self.size)
     }
     fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
+        // rounding up by adding first would overflow near `u32::MAX`
+        size / BLOCK_SZ as u32 + (size % BLOCK_SZ as u32 != 0) as u32
     }
     /// Return number of blocks needed include indirect1/2.
     pub fn total_blocks(size: u32) -> u32 {
@@ -116,180 +151,347 @@ impl DiskInode {
         }
         total as u32
     }
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
-    }
+    /// Return the block behind data block `inner_id`, or 0 for a hole.
+    ///
+    /// Block 0 holds the super block, so it is never part of a file and a
+    /// zero entry anywhere in the tree stands for blocks never written.
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
         let inner_id = inner_id as usize;
         if inner_id < INODE_DIRECT_COUNT {
             self.direct[inner_id]
         } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
+            Self::indirect_entry(self.indirect1, inner_id - INODE_DIRECT_COUNT, block_device)
         } else {
             let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect1: &IndirectBlock| {
-                    indirect1[last % INODE_INDIRECT1_COUNT]
-                })
+            let indirect1 =
+                Self::indirect_entry(self.indirect2, last / INODE_INDIRECT1_COUNT, block_device);
+            Self::indirect_entry(indirect1, last % INODE_INDIRECT1_COUNT, block_device)
         }
     }
-    pub fn increase_size(
+    /// Entry `index` of index block `block_id`, which may be a hole itself.
+    fn indirect_entry(block_id: u32, index: usize, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if block_id == 0 {
+            return 0;
+        }
+        get_block_cache(block_id as usize, Arc::clone(block_device))
+            .lock()
+            .read(0, |indirect_block: &IndirectBlock| indirect_block[index])
+    }
+    /// Return entry `index` of index block `block_id`, filling it from
+    /// `alloc` first if it is a hole.
+    fn map_entry(
+        block_id: u32,
+        index: usize,
+        alloc: &mut impl FnMut() -> u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        get_block_cache(block_id as usize, Arc::clone(block_device))
+            .lock()
+            .modify(0, |indirect_block: &mut IndirectBlock| {
+                if indirect_block[index] == 0 {
+                    indirect_block[index] = alloc();
+                }
+                indirect_block[index]
+            })
+    }
+    /// Return the block behind data block `inner_id`, filling the hole it
+    /// is in, index blocks included, with blocks from `alloc`.
+    ///
+    /// The blocks `alloc` hands out must be zeroed.
+    fn map_block(
         &mut self,
-        new_size: u32,
-        new_blocks: Vec<u32>,
+        inner_id: usize,
+        alloc: &mut impl FnMut() -> u32,
         block_device: &Arc<dyn BlockDevice>,
-    ) {
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
-        let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
+    ) -> u32 {
+        if inner_id < INODE_DIRECT_COUNT {
+            if self.direct[inner_id] == 0 {
+                self.direct[inner_id] = alloc();
+            }
+            return self.direct[inner_id];
         }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
+        if inner_id < INDIRECT1_BOUND {
+            if self.indirect1 == 0 {
+                self.indirect1 = alloc();
             }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
+            return Self::map_entry(
+                self.indirect1,
+                inner_id - INODE_DIRECT_COUNT,
+                alloc,
+                block_device,
+            );
         }
-        // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-                    current_blocks += 1;
+        if self.indirect2 == 0 {
+            self.indirect2 = alloc();
+        }
+        let last = inner_id - INDIRECT1_BOUND;
+        let indirect1 = Self::map_entry(
+            self.indirect2,
+            last / INODE_INDIRECT1_COUNT,
+            alloc,
+            block_device,
+        );
+        Self::map_entry(indirect1, last % INODE_INDIRECT1_COUNT, alloc, block_device)
+    }
+    /// Collect the blocks behind the first `data_blocks` data blocks, index
+    /// blocks included, stopping at the first block id `valid` rejects.
+    /// Return them with the number of data blocks reached.
+    ///
+    /// Holes are skipped and count as reached.
+    pub fn collect_blocks(
+        &self,
+        data_blocks: u32,
+        block_device: &Arc<dyn BlockDevice>,
+        valid: impl Fn(u32) -> bool,
+    ) -> (Vec<u32>, u32) {
+        let data_blocks = data_blocks as usize;
+        let mut v: Vec<u32> = Vec::new();
+        let read_indirect = |block_id: u32| {
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect_block: &IndirectBlock| *indirect_block)
+        };
+        // direct
+        for (i, &block_id) in self.direct.iter().enumerate().take(data_blocks) {
+            if block_id != 0 {
+                if !valid(block_id) {
+                    return (v, i as u32);
                 }
-            });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
+                v.push(block_id);
             }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
         }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    // fill current
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap();
-                        });
-                    // move to next
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
+        if data_blocks <= DIRECT_BOUND {
+            return (v, data_blocks as u32);
+        }
+        // indirect1
+        if self.indirect1 != 0 {
+            if !valid(self.indirect1) {
+                return (v, DIRECT_BOUND as u32);
+            }
+            v.push(self.indirect1);
+            let indirect1 = read_indirect(self.indirect1);
+            for i in DIRECT_BOUND..data_blocks.min(INDIRECT1_BOUND) {
+                let block_id = indirect1[i - DIRECT_BOUND];
+                if block_id != 0 {
+                    if !valid(block_id) {
+                        return (v, i as u32);
                     }
+                    v.push(block_id);
                 }
-            });
+            }
+        }
+        if data_blocks <= INDIRECT1_BOUND || self.indirect2 == 0 {
+            return (v, data_blocks as u32);
+        }
+        // indirect2
+        if !valid(self.indirect2) {
+            return (v, INDIRECT1_BOUND as u32);
+        }
+        v.push(self.indirect2);
+        let indirect2 = read_indirect(self.indirect2);
+        let mut indirect1 = [0u32; INODE_INDIRECT1_COUNT];
+        for i in INDIRECT1_BOUND..data_blocks {
+            let last = i - INDIRECT1_BOUND;
+            if last % INODE_INDIRECT1_COUNT == 0 {
+                let sub = indirect2[last / INODE_INDIRECT1_COUNT];
+                if sub == 0 {
+                    indirect1 = [0u32; INODE_INDIRECT1_COUNT];
+                } else if !valid(sub) {
+                    return (v, i as u32);
+                } else {
+                    v.push(sub);
+                    indirect1 = read_indirect(sub);
+                }
+            }
+            let block_id = indirect1[last % INODE_INDIRECT1_COUNT];
+            if block_id != 0 {
+                if !valid(block_id) {
+                    return (v, i as u32);
+                }
+                v.push(block_id);
+            }
+        }
+        (v, data_blocks as u32)
+    }
+    /// Cut the size down to `data_blocks` blocks and clear every entry past
+    /// them, without following those entries. For a tree which is only
+    /// valid up to there; the blocks dropped are left allocated.
+    pub fn cut_blocks(&mut self, data_blocks: u32, block_device: &Arc<dyn BlockDevice>) {
+        let data_blocks = data_blocks as usize;
+        self.size = self.size.min((data_blocks * BLOCK_SZ) as u32);
+        let clear_from = |block_id: u32, start: usize| {
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |indirect_block: &mut IndirectBlock| {
+                    indirect_block[start..].fill(0);
+                });
+        };
+        self.direct
+            .iter_mut()
+            .skip(data_blocks)
+            .for_each(|entry| *entry = 0);
+        if data_blocks <= DIRECT_BOUND {
+            self.indirect1 = 0;
+        } else if self.indirect1 != 0 {
+            clear_from(
+                self.indirect1,
+                (data_blocks - DIRECT_BOUND).min(INODE_INDIRECT1_COUNT),
+            );
+        }
+        if data_blocks <= INDIRECT1_BOUND {
+            self.indirect2 = 0;
+        } else if self.indirect2 != 0 {
+            let last = data_blocks - INDIRECT1_BOUND;
+            let (a, b) = (last / INODE_INDIRECT1_COUNT, last % INODE_INDIRECT1_COUNT);
+            if b > 0 {
+                // the low-level indirect1 block at a is reached, so it is valid
+                let sub = Self::indirect_entry(self.indirect2, a, block_device);
+                if sub != 0 {
+                    clear_from(sub, b);
+                }
+                clear_from(self.indirect2, a + 1);
+            } else {
+                clear_from(self.indirect2, a);
+            }
+        }
+    }
+    /// Grow to `new_size` without allocating anything: the blocks past the
+    /// old end are holes until they are written.
+    pub fn increase_size(&mut self, new_size: u32) {
+        assert!(new_size >= self.size);
+        self.size = new_size;
+    }
+    /// Back the bytes in `start..end` with data blocks, filling the holes
+    /// among them and the index blocks they need with blocks from `alloc`.
+    ///
+    /// The blocks `alloc` hands out must be zeroed.
+    pub fn alloc_blocks(
+        &mut self,
+        start: usize,
+        end: usize,
+        mut alloc: impl FnMut() -> u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        assert!(end <= self.size as usize);
+        if start >= end {
+            return;
+        }
+        for inner_id in start / BLOCK_SZ..(end + BLOCK_SZ - 1) / BLOCK_SZ {
+            self.map_block(inner_id, &mut alloc, block_device);
+        }
     }
 
     /// Clear size to zero and return blocks that should be deallocated.
     ///
     /// We will clear the block contents to zero later.
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        self.decrease_size(0, block_device)
+    }
+    /// Shrink size to `new_size` and return blocks that should be deallocated,
+    /// including indirect blocks which are no longer needed.
+    ///
+    /// The tail of the last remaining block is zeroed, so that growing the
+    /// file again does not bring back the truncated bytes. The entries past
+    /// the new end are cleared, so that they read as holes.
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let data_blocks = self.data_blocks() as usize;
+        let new_data_blocks = Self::_data_blocks(new_size) as usize;
+        let tail = new_size as usize % BLOCK_SZ;
+        if tail > 0 {
+            let block_id = self.get_block_id(new_data_blocks as u32 - 1, block_device);
+            if block_id != 0 {
+                get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .modify(0, |data_block: &mut DataBlock| {
+                        data_block[tail..].iter_mut().for_each(|p| *p = 0);
+                    });
+            }
+        }
+        self.size = new_size;
         let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
         // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
+        for entry in self
+            .direct
+            .iter_mut()
+            .take(data_blocks.min(INODE_DIRECT_COUNT))
+            .skip(new_data_blocks)
+        {
+            if *entry != 0 {
+                v.push(*entry);
+                *entry = 0;
+            }
         }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
+        // indirect1
+        if data_blocks > DIRECT_BOUND && self.indirect1 != 0 {
+            Self::unmap_entries(
+                self.indirect1,
+                new_data_blocks.clamp(DIRECT_BOUND, INDIRECT1_BOUND) - DIRECT_BOUND,
+                data_blocks.min(INDIRECT1_BOUND) - DIRECT_BOUND,
+                &mut v,
+                block_device,
+            );
+            if new_data_blocks <= DIRECT_BOUND {
+                v.push(self.indirect1);
+                self.indirect1 = 0;
+            }
+        }
+        if data_blocks <= INDIRECT1_BOUND || self.indirect2 == 0 {
             return v;
         }
-        // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+        // low-level indirect1 blocks from a0 on lose entries in [first, last)
+        let first = new_data_blocks.max(INDIRECT1_BOUND) - INDIRECT1_BOUND;
+        let last = data_blocks - INDIRECT1_BOUND;
+        let a0 = first / INODE_INDIRECT1_COUNT;
+        let a1 = (last + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect1[current_blocks]);
-                    //indirect1[current_blocks] = 0;
-                    current_blocks += 1;
+            .modify(0, |indirect2: &mut IndirectBlock| {
+                for (a, entry) in indirect2.iter_mut().enumerate().take(a1).skip(a0) {
+                    if *entry == 0 {
+                        continue;
+                    }
+                    let base = a * INODE_INDIRECT1_COUNT;
+                    let start = first.max(base) - base;
+                    let end = last.min(base + INODE_INDIRECT1_COUNT) - base;
+                    Self::unmap_entries(*entry, start, end, &mut v, block_device);
+                    // no longer needed unless it still maps blocks before first
+                    if start == 0 {
+                        v.push(*entry);
+                        *entry = 0;
+                    }
                 }
             });
-        self.indirect1 = 0;
         // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
+        if new_data_blocks <= INDIRECT1_BOUND {
             v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
+            self.indirect2 = 0;
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+        v
+    }
+    /// Clear the entries `start..end` of index block `block_id`, pushing the
+    /// blocks they mapped onto `v`.
+    fn unmap_entries(
+        block_id: u32,
+        start: usize,
+        end: usize,
+        v: &mut Vec<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        get_block_cache(block_id as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                    //indirect2[a1] = 0;
+            .modify(0, |indirect_block: &mut IndirectBlock| {
+                for entry in indirect_block[start..end].iter_mut() {
+                    if *entry != 0 {
+                        v.push(*entry);
+                        *entry = 0;
+                    }
                 }
             });
-        self.indirect2 = 0;
-        v
     }
     pub fn read_at(
         &self,
@@ -311,15 +513,16 @@ impl DiskInode {
             // read and update read size
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            match self.get_block_id(start_block as u32, block_device) {
+                // a hole reads as zeros
+                0 => dst.fill(0),
+                block_id => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    }),
+            }
             read_size += block_read_size;
             // move to next block
             if end_current_block == end {
@@ -330,7 +533,8 @@ impl DiskInode {
         }
         read_size
     }
-    /// File size must be adjusted before.
+    /// File size must be adjusted and the blocks allocated with
+    /// `alloc_blocks` before.
     pub fn write_at(
         &mut self,
         offset: usize,
@@ -340,6 +544,9 @@ impl DiskInode {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
+        if start == end {
+            return 0;
+        }
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
         loop {
@@ -348,16 +555,16 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                let src = &buf[write_size..write_size + block_write_size];
-                let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
-                dst.copy_from_slice(src);
-            });
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            assert!(block_id != 0, "write to a hole");
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| {
+                    let src = &buf[write_size..write_size + block_write_size];
+                    let dst =
+                        &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
+                    dst.copy_from_slice(src);
+                });
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -370,40 +577,102 @@ impl DiskInode {
     }
 }
 
-#[repr(C)]
+/// A directory entry of variable length, in the style of ext2.
+///
+/// On disk it is an 8-byte header (`inode_number: u32`, `rec_len: u16`,
+/// `name_len: u8` and a reserved byte) followed by the name, padded to a
+/// multiple of 4 bytes. Entries never cross a block and the `rec_len`s
+/// of the entries in a block add up to `BLOCK_SZ`, so any slack after an
+/// entry belongs to it. An entry with an empty name is unused.
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
     inode_number: u32,
+    rec_len: u16,
+    name_len: u8,
+    name: [u8; NAME_LENGTH_LIMIT],
 }
 
-pub const DIRENT_SZ: usize = 32;
+pub const DIRENT_HEADER_SZ: usize = 8;
 
 impl DirEntry {
-    pub fn empty() -> Self {
-        Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
-            inode_number: 0,
-        }
+    pub fn empty(rec_len: usize) -> Self {
+        Self::new("", 0, rec_len)
     }
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
+    pub fn new(name: &str, inode_number: u32, rec_len: usize) -> Self {
+        assert!(name.len() <= NAME_LENGTH_LIMIT);
+        assert!(rec_len >= Self::size_for(name.len()) && rec_len <= BLOCK_SZ);
+        let mut bytes = [0u8; NAME_LENGTH_LIMIT];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
         Self {
-            name: bytes,
             inode_number,
+            rec_len: rec_len as u16,
+            name_len: name.len() as u8,
+            name: bytes,
+        }
+    }
+    /// Parse the entry at the start of `bytes`.
+    pub fn read_from(bytes: &[u8]) -> Self {
+        let name_len = bytes[6] as usize;
+        let mut name = [0u8; NAME_LENGTH_LIMIT];
+        name[..name_len].copy_from_slice(&bytes[DIRENT_HEADER_SZ..DIRENT_HEADER_SZ + name_len]);
+        Self {
+            inode_number: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            rec_len: u16::from_le_bytes([bytes[4], bytes[5]]),
+            name_len: name_len as u8,
+            name,
         }
     }
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
+    /// Store the entry at the start of `bytes`, which must hold `size()` bytes.
+    pub fn write_to(&self, bytes: &mut [u8]) {
+        bytes[0..4].copy_from_slice(&self.inode_number.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.rec_len.to_le_bytes());
+        bytes[6] = self.name_len;
+        bytes[7] = 0;
+        let name_len = self.name_len as usize;
+        bytes[DIRENT_HEADER_SZ..DIRENT_HEADER_SZ + name_len]
+            .copy_from_slice(&self.name[..name_len]);
+        bytes[DIRENT_HEADER_SZ + name_len..self.size()].fill(0);
     }
-    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
+    /// Bytes taken by an entry with a name of `name_len` bytes, without slack.
+    pub fn size_for(name_len: usize) -> usize {
+        (DIRENT_HEADER_SZ + name_len + 3) & !3
+    }
+    pub fn size(&self) -> usize {
+        Self::size_for(self.name_len as usize)
+    }
+    pub fn rec_len(&self) -> usize {
+        self.rec_len as usize
+    }
+    pub fn set_rec_len(&mut self, rec_len: usize) {
+        assert!(rec_len >= self.size());
+        self.rec_len = rec_len as u16;
     }
     pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap()
     }
     pub fn inode_number(&self) -> u32 {
         self.inode_number
     }
+    pub fn is_empty(&self) -> bool {
+        self.name_len == 0
+    }
+    /// Remove the entry at `pos` of the directory block `block`. Its space
+    /// goes to the entry before it, or it stays as an unused entry if it
+    /// comes first in the block.
+    pub fn remove(block: &mut [u8; BLOCK_SZ], pos: usize) {
+        let rec_len = Self::read_from(&block[pos..]).rec_len();
+        let mut prev_pos = None;
+        let mut current = 0;
+        while current < pos {
+            prev_pos = Some(current);
+            current += Self::read_from(&block[current..]).rec_len();
+        }
+        match prev_pos {
+            Some(prev_pos) => {
+                let mut prev = Self::read_from(&block[prev_pos..]);
+                prev.set_rec_len(prev.rec_len() + rec_len);
+                prev.write_to(&mut block[prev_pos..]);
+            }
+            None => Self::empty(rec_len).write_to(&mut block[pos..]),
+        }
+    }
 }
@@ -6,13 +6,23 @@ mod bitmap;
 mod block_cache;
 mod block_dev;
 mod efs;
+mod fsck;
+mod journal;
 mod layout;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::{
+    block_cache_end_transaction, block_cache_is_dirty, block_cache_reset, get_block_cache,
+    BlockCache,
+};
+pub use block_cache::{block_cache_set_capacity, block_cache_sync_all};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use fsck::{fsck, FsckProblem};
+use journal::Journal;
+pub use journal::JOURNAL_BLOCKS;
 use layout::*;
-pub use vfs::Inode;
+pub use layout::{DiskInodeType, MAX_FILE_SIZE, NAME_LENGTH_LIMIT};
+pub use vfs::{DirEntryInfo, Inode, Metadata};
@@ -1,105 +1,290 @@
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    block_cache_end_transaction, block_cache_is_dirty, block_cache_sync_all, get_block_cache,
+    BlockCache, BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, BLOCK_SZ,
+    MAX_FILE_SIZE, NAME_LENGTH_LIMIT,
 };
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::{TryFrom, TryInto};
+use core::sync::atomic::{self, AtomicBool};
 use spin::{Mutex, MutexGuard};
 
+/// Most data blocks a single transaction allocates, frees or writes, which
+/// keeps every transaction well within the journal.
+const BLOCKS_PER_TRANSACTION: usize = 16;
+
+/// Metadata of an inode, as kept in its `DiskInode`.
+pub struct Metadata {
+    pub inode_id: u32,
+    pub type_: DiskInodeType,
+    pub size: u32,
+    /// Data and index blocks taken, which holes do not count towards.
+    pub blocks: u32,
+    pub nlink: u32,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// One entry of a directory, as returned by `Inode::read_dir`.
+pub struct DirEntryInfo {
+    pub name: String,
+    pub inode_id: u32,
+    pub type_: DiskInodeType,
+    /// Where to resume the listing after this entry.
+    pub next_offset: usize,
+}
+
+/// An inode in use. There is only one per inode id at a time, handed out
+/// by `EasyFileSystem::get_inode`.
 pub struct Inode {
+    inode_id: u32,
     block_id: usize,
     block_offset: usize,
+    /// The block holding the `DiskInode`, kept in the cache while in use.
+    block: Arc<Mutex<BlockCache>>,
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
+    /// Set once the last link is gone while still in use. The inode is
+    /// released when it is dropped.
+    orphan: AtomicBool,
 }
 
 impl Inode {
     /// We should not acquire efs lock here.
-    pub fn new(
+    pub(crate) fn new(
+        inode_id: u32,
         block_id: u32,
         block_offset: usize,
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
         Self {
+            inode_id,
             block_id: block_id as usize,
             block_offset,
+            block: get_block_cache(block_id as usize, Arc::clone(&block_device)),
             fs,
             block_device,
+            orphan: AtomicBool::new(false),
         }
     }
 
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
-            .lock()
-            .read(self.block_offset, f)
+        self.block.lock().read(self.block_offset, f)
     }
 
     fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
-        get_block_cache(self.block_id, Arc::clone(&self.block_device))
-            .lock()
-            .modify(self.block_offset, f)
+        self.block.lock().modify(self.block_offset, f)
     }
 
-    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+    /// Walk the entries of this directory, unused ones included, and return
+    /// the first one that `pred` accepts together with its byte offset.
+    fn scan_dirents(
+        &self,
+        disk_inode: &DiskInode,
+        mut pred: impl FnMut(usize, &DirEntry) -> bool,
+    ) -> Option<(usize, DirEntry)> {
         // assert it is a directory
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device,),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
+        let mut block = [0u8; BLOCK_SZ];
+        for block_start in (0..disk_inode.size as usize).step_by(BLOCK_SZ) {
+            disk_inode.read_at(block_start, &mut block, &self.block_device);
+            let mut pos = 0;
+            while pos < BLOCK_SZ {
+                let dirent = DirEntry::read_from(&block[pos..]);
+                if pred(block_start + pos, &dirent) {
+                    return Some((block_start + pos, dirent));
+                }
+                assert!(dirent.rec_len() > 0, "corrupted directory entry");
+                pos += dirent.rec_len();
             }
         }
         None
     }
 
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
+    /// Run `f` on the directory block holding byte `offset`, with the position
+    /// of `offset` inside it, and write the block back.
+    fn modify_dirent_block(
+        &self,
+        offset: usize,
+        dir_inode: &mut DiskInode,
+        f: impl FnOnce(&mut [u8; BLOCK_SZ], usize),
+    ) {
+        let block_start = offset - offset % BLOCK_SZ;
+        let mut block = [0u8; BLOCK_SZ];
+        dir_inode.read_at(block_start, &mut block, &self.block_device);
+        f(&mut block, offset % BLOCK_SZ);
+        dir_inode.write_at(block_start, &block, &self.block_device);
+    }
+
+    /// Return (dirent offset, inode id) of the entry called `name`.
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
+        self.scan_dirents(disk_inode, |_, dirent| {
+            !dirent.is_empty() && dirent.name() == name
         })
+        .map(|(offset, dirent)| (offset, dirent.inode_number()))
     }
 
-    fn increase_size(
+    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        self.find_dirent(name, disk_inode)
+            .map(|(_, inode_id)| inode_id)
+    }
+
+    /// A directory is empty if it holds nothing but "." and "..".
+    fn is_empty_dir(&self, disk_inode: &DiskInode) -> bool {
+        self.scan_dirents(disk_inode, |_, dirent| {
+            !dirent.is_empty() && dirent.name() != "." && dirent.name() != ".."
+        })
+        .is_none()
+    }
+
+    fn get_inode(&self, inode_id: u32, fs: &mut MutexGuard<EasyFileSystem>) -> Arc<Inode> {
+        fs.get_inode(&self.fs, inode_id)
+    }
+
+    /// Look up `name` in this directory. Return `None` if this is not a directory.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let mut fs = self.fs.lock();
+        let inode_id = self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            self.find_inode_id(name, disk_inode)
+        })?;
+        Some(self.get_inode(inode_id, &mut fs))
+    }
+
+    pub fn is_dir(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
+    }
+
+    /// Make `start..end` ready to be written: grow to `end` if shorter and
+    /// fill the holes in between with fresh blocks. `end` must not be past
+    /// `MAX_FILE_SIZE`.
+    fn prepare_write(
         &self,
-        new_size: u32,
+        start: usize,
+        end: usize,
         disk_inode: &mut DiskInode,
         fs: &mut MutexGuard<EasyFileSystem>,
     ) {
-        if new_size < disk_inode.size {
-            return;
+        assert!(end <= MAX_FILE_SIZE, "write past the largest file");
+        let end_size = u32::try_from(end).unwrap();
+        if end_size > disk_inode.size {
+            disk_inode.increase_size(end_size);
         }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::new();
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
+        disk_inode.alloc_blocks(start, end, || fs.alloc_data(), &self.block_device);
+    }
+
+    /// Grow or shrink to exactly `new_size` bytes. Growing leaves a hole,
+    /// shrinking ends a transaction after every `BLOCKS_PER_TRANSACTION`
+    /// data blocks.
+    fn resize(&self, new_size: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        assert!(new_size as usize <= MAX_FILE_SIZE);
+        let step = (BLOCKS_PER_TRANSACTION * BLOCK_SZ) as u32;
+        loop {
+            let done = self.modify_disk_inode(|disk_inode| {
+                let size = disk_inode.size;
+                match size.cmp(&new_size) {
+                    Ordering::Less => {
+                        disk_inode.increase_size(new_size);
+                    }
+                    Ordering::Greater => {
+                        let step_size = new_size.max(size.saturating_sub(step));
+                        let blocks_dealloc =
+                            disk_inode.decrease_size(step_size, &self.block_device);
+                        for block in blocks_dealloc.into_iter() {
+                            fs.dealloc_data(block);
+                        }
+                    }
+                    Ordering::Equal => return true,
+                }
+                false
+            });
+            if done {
+                break;
+            }
+            // the block of this inode must not be locked while committing
+            block_cache_end_transaction();
         }
-        disk_inode.increase_size(new_size, v, &self.block_device);
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+    /// Put a dirent into the first entry with enough room to spare, splitting
+    /// it if it is in use, or into a new block if there is no such entry.
+    fn add_dirent(
+        &self,
+        name: &str,
+        inode_id: u32,
+        dir_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let needed = DirEntry::size_for(name.len());
+        let slot = self.scan_dirents(dir_inode, |_, dirent| {
+            let used = if dirent.is_empty() { 0 } else { dirent.size() };
+            dirent.rec_len() - used >= needed
+        });
+        match slot {
+            Some((offset, mut dirent)) => {
+                self.modify_dirent_block(offset, dir_inode, |block, pos| {
+                    if dirent.is_empty() {
+                        DirEntry::new(name, inode_id, dirent.rec_len()).write_to(&mut block[pos..]);
+                    } else {
+                        let rec_len = dirent.rec_len();
+                        let used = dirent.size();
+                        dirent.set_rec_len(used);
+                        dirent.write_to(&mut block[pos..]);
+                        DirEntry::new(name, inode_id, rec_len - used)
+                            .write_to(&mut block[pos + used..]);
+                    }
+                });
+            }
+            None => {
+                let offset = dir_inode.size as usize;
+                self.prepare_write(offset, offset + BLOCK_SZ, dir_inode, fs);
+                let mut block = [0u8; BLOCK_SZ];
+                DirEntry::new(name, inode_id, BLOCK_SZ).write_to(&mut block);
+                dir_inode.write_at(offset, &block, &self.block_device);
+            }
+        }
+        dir_inode.touch(fs.now());
+    }
+
+    /// Remove the dirent at `offset`. Its space goes to the entry before it in
+    /// the same block, or it is left as an unused entry if it is the first one.
+    fn remove_dirent(&self, offset: usize, dir_inode: &mut DiskInode, now: u64) {
+        self.modify_dirent_block(offset, dir_inode, DirEntry::remove);
+        dir_inode.touch(now);
+    }
+
+    fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= NAME_LENGTH_LIMIT && !name.contains('/')
+    }
+
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if !Self::is_valid_name(name) {
+            return None;
+        }
         let mut fs = self.fs.lock();
         let op = |root_inode: &mut DiskInode| {
             // assert it is a directory
             assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+            // has the file been created, or the directory been removed?
+            root_inode.nlink == 0 || self.find_inode_id(name, root_inode).is_some()
         };
-        if self.modify_disk_inode(op).is_some() {
+        if self.modify_disk_inode(op) {
             return None;
         }
         // create a new file
@@ -107,80 +292,477 @@ impl Inode {
         let new_inode_id = fs.alloc_inode();
         // initialize inode
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
+        let type_is_dir = type_ == DiskInodeType::Directory;
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+                new_inode.initialize(type_);
+                new_inode.touch(fs.now());
+                if type_is_dir {
+                    fs.init_dir_entries(new_inode, new_inode_id, self.inode_id);
+                }
             });
         self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
+            self.add_dirent(name, new_inode_id, root_inode, &mut fs);
+            // the ".." of a new directory links back here
+            if type_is_dir {
+                root_inode.nlink += 1;
+            }
         });
 
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
+        block_cache_end_transaction();
         // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
+        Some(self.get_inode(new_inode_id, &mut fs))
         // release efs lock automatically by compiler
     }
 
+    /// Create a regular file called `name` in this directory.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    /// Create a directory called `name`, already holding "." and "..".
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    /// Create a symbolic link called `name` pointing at `target`.
+    pub fn create_symlink(&self, name: &str, target: &str) -> Option<Arc<Inode>> {
+        if target.is_empty() {
+            return None;
+        }
+        let inode = self.create_inode(name, DiskInodeType::Symlink)?;
+        inode.write_at(0, target.as_bytes());
+        Some(inode)
+    }
+
+    /// Return the target path if this is a symbolic link.
+    pub fn read_link(&self) -> Option<String> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_symlink() {
+                return None;
+            }
+            let mut target = vec![0u8; disk_inode.size as usize];
+            disk_inode.read_at(0, &mut target, &self.block_device);
+            String::from_utf8(target).ok()
+        })
+    }
+
+    /// Add the entry `name` for the existing file `inode`, which gains a link.
+    ///
+    /// Directories cannot be linked.
+    pub fn link(&self, name: &str, inode: &Inode) -> bool {
+        if !Self::is_valid_name(name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let exists = self.read_disk_inode(|dir_inode| {
+            !dir_inode.is_dir()
+                || dir_inode.nlink == 0
+                || self.find_inode_id(name, dir_inode).is_some()
+        });
+        if exists {
+            return false;
+        }
+        let linked = inode.modify_disk_inode(|disk_inode| {
+            if disk_inode.is_dir() {
+                return false;
+            }
+            disk_inode.nlink += 1;
+            disk_inode.ctime = fs.now();
+            true
+        });
+        if !linked {
+            return false;
+        }
+        self.modify_disk_inode(|dir_inode| {
+            self.add_dirent(name, inode.inode_id, dir_inode, &mut fs);
+        });
+        block_cache_end_transaction();
+        true
+    }
+
+    /// Remove the entry `name` and drop one link from the inode behind it.
+    /// The blocks and the inode itself are released once no links are left.
+    ///
+    /// Directories are only removed if `is_dir` is set and they are empty,
+    /// and files only if it is not.
+    fn remove_entry(&self, name: &str, is_dir: bool) -> bool {
+        if name == "." || name == ".." {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let orphan = match self.remove_entry_locked(name, is_dir, &mut fs) {
+            Some(orphan) => orphan,
+            None => return false,
+        };
+        block_cache_end_transaction();
+        if let Some(inode_id) = orphan {
+            self.release_inode(inode_id, fs);
+        }
+        true
+    }
+
+    /// Body of `remove_entry`, for callers already holding the efs lock.
+    /// Nothing is changed if it fails.
+    ///
+    /// On success, return the inode which lost its last link, if any. It has
+    /// to be released with `release_inode` after the removal's transaction.
+    fn remove_entry_locked(
+        &self,
+        name: &str,
+        is_dir: bool,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) -> Option<Option<u32>> {
+        let dirent = self.read_disk_inode(|dir_inode| {
+            if !dir_inode.is_dir() {
+                return None;
+            }
+            self.find_dirent(name, dir_inode)
+        });
+        let (offset, inode_id) = dirent?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        // None if the entry cannot be removed, otherwise whether the inode is gone
+        let released = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                if disk_inode.is_dir() != is_dir || (is_dir && !self.is_empty_dir(disk_inode)) {
+                    return None;
+                }
+                // an empty directory loses its parent's entry and its own "." at once
+                disk_inode.nlink -= if is_dir { 2 } else { 1 };
+                disk_inode.ctime = fs.now();
+                Some(disk_inode.nlink == 0)
+            })?;
+        self.modify_disk_inode(|dir_inode| {
+            self.remove_dirent(offset, dir_inode, fs.now());
+            // the ".." of the removed directory no longer links here
+            if is_dir {
+                dir_inode.nlink -= 1;
+            }
+        });
+        Some(if released { Some(inode_id) } else { None })
+    }
+
+    /// Release `inode_id`, which has no links left, or leave that to its
+    /// last user if it is still in use.
+    fn release_inode(&self, inode_id: u32, mut fs: MutexGuard<EasyFileSystem>) {
+        match fs.open_inode(inode_id) {
+            Some(inode) => {
+                inode.orphan.store(true, atomic::Ordering::Relaxed);
+                // it may be the last one by now, and dropping it takes the lock
+                drop(fs);
+                drop(inode);
+            }
+            None => self.get_inode(inode_id, &mut fs).free(&mut fs),
+        }
+    }
+
+    /// Free the blocks and then the inode itself.
+    ///
+    /// Large files take several transactions, so a crash in between leaves
+    /// the inode allocated with no links, but never a block owned twice.
+    fn free(&self, fs: &mut MutexGuard<EasyFileSystem>) {
+        self.resize(0, fs);
+        fs.dealloc_inode(self.inode_id);
+        block_cache_end_transaction();
+    }
+
+    fn disk_inode_type(&self, inode_id: u32, fs: &MutexGuard<EasyFileSystem>) -> DiskInodeType {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| disk_inode.type_())
+    }
+
+    /// Whether the directory `inode_id` is `ancestor_id` or lies below it.
+    fn is_in_subtree(
+        &self,
+        inode_id: u32,
+        ancestor_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+    ) -> bool {
+        let mut current = inode_id;
+        loop {
+            if current == ancestor_id {
+                return true;
+            }
+            if current == 0 {
+                return false;
+            }
+            let (block_id, block_offset) = fs.get_disk_inode_pos(current);
+            current = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(block_offset, |disk_inode: &DiskInode| {
+                    self.find_inode_id("..", disk_inode).unwrap()
+                });
+        }
+    }
+
+    /// Move the entry `old_name` of this directory to `new_name` in `new_dir`,
+    /// which may be this directory again.
+    ///
+    /// An existing `new_name` is replaced, as long as it is of the same kind
+    /// and, for a directory, empty. A directory cannot be moved below itself.
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        if [old_name, new_name]
+            .iter()
+            .any(|name| *name == "." || *name == "..")
+            || !Self::is_valid_name(new_name)
+        {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let dirent = self.read_disk_inode(|dir_inode| {
+            if !dir_inode.is_dir() {
+                return None;
+            }
+            self.find_dirent(old_name, dir_inode)
+        });
+        let (offset, inode_id) = match dirent {
+            Some(dirent) => dirent,
+            None => return false,
+        };
+        let target = new_dir.read_disk_inode(|dir_inode| {
+            if !dir_inode.is_dir() || dir_inode.nlink == 0 {
+                return None;
+            }
+            Some(self.find_inode_id(new_name, dir_inode))
+        });
+        let target_id = match target {
+            Some(target_id) => target_id,
+            None => return false,
+        };
+        if target_id == Some(inode_id) {
+            return true;
+        }
+        let is_dir = self.disk_inode_type(inode_id, &fs) == DiskInodeType::Directory;
+        let same_dir = self.inode_id == new_dir.inode_id;
+        if is_dir && !same_dir && self.is_in_subtree(new_dir.inode_id, inode_id, &fs) {
+            return false;
+        }
+        // checks the kind of the replaced entry and changes nothing if it fails
+        let mut orphan = None;
+        if target_id.is_some() {
+            match new_dir.remove_entry_locked(new_name, is_dir, &mut fs) {
+                Some(replaced) => orphan = replaced,
+                None => return false,
+            }
+        }
+        let now = fs.now();
+        if same_dir {
+            // the new name may not fit where the old one was
+            self.modify_disk_inode(|dir_inode| {
+                self.remove_dirent(offset, dir_inode, now);
+                self.add_dirent(new_name, inode_id, dir_inode, &mut fs);
+            });
+        } else {
+            new_dir.modify_disk_inode(|dir_inode| {
+                new_dir.add_dirent(new_name, inode_id, dir_inode, &mut fs);
+                if is_dir {
+                    dir_inode.nlink += 1;
+                }
+            });
+            self.modify_disk_inode(|dir_inode| {
+                self.remove_dirent(offset, dir_inode, now);
+                if is_dir {
+                    dir_inode.nlink -= 1;
+                }
+            });
+        }
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.ctime = now;
+                // a moved directory has a new parent
+                if is_dir && !same_dir {
+                    let (parent_offset, _) = self.find_dirent("..", disk_inode).unwrap();
+                    self.modify_dirent_block(parent_offset, disk_inode, |block, pos| {
+                        let rec_len = DirEntry::read_from(&block[pos..]).rec_len();
+                        DirEntry::new("..", new_dir.inode_id, rec_len).write_to(&mut block[pos..]);
+                    });
+                }
+            });
+        block_cache_end_transaction();
+        if let Some(orphan_id) = orphan {
+            self.release_inode(orphan_id, fs);
+        }
+        true
+    }
+
+    /// Remove the empty directory `name` and release its blocks and inode.
+    pub fn remove_dir(&self, name: &str) -> bool {
+        self.remove_entry(name, true)
+    }
+
+    /// Remove the entry `name` of a file, releasing the file with its last link.
+    pub fn unlink(&self, name: &str) -> bool {
+        self.remove_entry(name, false)
+    }
+
+    pub fn nlink(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| Metadata {
+            inode_id: self.inode_id,
+            type_: disk_inode.type_(),
+            size: disk_inode.size,
+            blocks: disk_inode
+                .collect_blocks(disk_inode.data_blocks(), &self.block_device, |_| true)
+                .0
+                .len() as u32,
+            nlink: disk_inode.nlink,
+            mtime: disk_inode.mtime,
+            ctime: disk_inode.ctime,
+        })
+    }
+
+    /// List the names in this directory, leaving out "." and "..".
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
             let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
-            }
+            self.scan_dirents(disk_inode, |_, dirent| {
+                if !dirent.is_empty() && dirent.name() != "." && dirent.name() != ".." {
+                    v.push(String::from(dirent.name()));
+                }
+                false
+            });
             v
         })
     }
 
+    /// Return the first entry of this directory at or after byte `offset`,
+    /// "." and ".." included. Return `None` at the end or if this is not a directory.
+    pub fn read_dir(&self, offset: usize) -> Option<DirEntryInfo> {
+        let fs = self.fs.lock();
+        let (name, inode_id, next_offset) = self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            self.scan_dirents(disk_inode, |dirent_offset, dirent| {
+                dirent_offset >= offset && !dirent.is_empty()
+            })
+            .map(|(dirent_offset, dirent)| {
+                (
+                    String::from(dirent.name()),
+                    dirent.inode_number(),
+                    dirent_offset + dirent.rec_len(),
+                )
+            })
+        })?;
+        // the entry may live in the same block as this inode, so look it up
+        // only after the block above has been released
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let type_ = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| disk_inode.type_());
+        Some(DirEntryInfo {
+            name,
+            inode_id,
+            type_,
+            next_offset,
+        })
+    }
+
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
     }
 
+    /// Write `buf` at `offset`, growing the file as needed. Only the blocks
+    /// written are allocated, any gap before `offset` is left as a hole.
+    /// Nothing goes past `MAX_FILE_SIZE`, the write is cut short there.
+    ///
+    /// Large writes are split into several transactions, so a crash may
+    /// leave a prefix of `buf` written. Nothing is durable before the next
+    /// `block_cache_sync_all`.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        if offset > MAX_FILE_SIZE {
+            return 0;
+        }
+        let buf = &buf[..buf.len().min(MAX_FILE_SIZE - offset)];
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
-        size
+        let mut written = 0;
+        loop {
+            let end = buf.len().min(written + BLOCKS_PER_TRANSACTION * BLOCK_SZ);
+            written += self.modify_disk_inode(|disk_inode| {
+                self.prepare_write(offset + written, offset + end, disk_inode, &mut fs);
+                disk_inode.touch(fs.now());
+                disk_inode.write_at(offset + written, &buf[written..end], &self.block_device)
+            });
+            block_cache_end_transaction();
+            if written == buf.len() {
+                break;
+            }
+        }
+        written
     }
 
-    pub fn clear(&self) {
+    /// Cut the file down to or extend it to `new_size` bytes, false if
+    /// that is past `MAX_FILE_SIZE`.
+    ///
+    /// Bytes past the old end of the file read as zero.
+    pub fn truncate(&self, new_size: u32) -> bool {
+        if new_size as usize > MAX_FILE_SIZE {
+            return false;
+        }
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
-            }
+        self.resize(new_size, &mut fs);
+        self.modify_disk_inode(|disk_inode| disk_inode.touch(fs.now()));
+        block_cache_end_transaction();
+        true
+    }
+
+    pub fn clear(&self) {
+        self.truncate(0);
+    }
+
+    /// Make the changes to this inode durable. With `data_only`, changes to
+    /// its metadata other than the size may be left behind.
+    ///
+    /// Transactions are only ever committed together, so if anything of
+    /// this inode is not durable yet, everything done so far is committed.
+    pub fn sync(&self, data_only: bool) {
+        let _fs = self.fs.lock();
+        let (blocks, size) = self.read_disk_inode(|disk_inode| {
+            let (blocks, _) =
+                disk_inode.collect_blocks(disk_inode.data_blocks(), &self.block_device, |_| true);
+            (blocks, disk_inode.size)
         });
-        block_cache_sync_all();
+        let dirty = blocks
+            .into_iter()
+            .any(|block_id| block_cache_is_dirty(block_id as usize))
+            || block_cache_is_dirty(self.block_id) && (!data_only || self.durable_size() != size);
+        if dirty {
+            block_cache_sync_all();
+        }
+    }
+
+    /// The size as of the last commit, which is what the disk holds.
+    fn durable_size(&self) -> u32 {
+        let mut block = [0u8; BLOCK_SZ];
+        self.block_device.read_block(self.block_id, &mut block);
+        // the size comes first in a `DiskInode`
+        u32::from_ne_bytes(
+            block[self.block_offset..self.block_offset + 4]
+                .try_into()
+                .unwrap(),
+        )
+    }
+}
+
+impl Drop for Inode {
+    fn drop(&mut self) {
+        if self.orphan.load(atomic::Ordering::Relaxed) {
+            let mut fs = self.fs.lock();
+            self.free(&mut fs);
+        }
     }
 }
@@ -13,6 +13,8 @@ pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
 
 pub const VIRT_PLIC: usize = 0xC00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
+/// The swap device, on virtio-mmio-bus.2. It is polled, its irq 3 stays disabled.
+pub const VIRTIO_SWAP: usize = 0x1000_3000;
 #[allow(unused)]
 pub const VIRTGPU_XRES: u32 = 1280;
 #[allow(unused)]
@@ -3,9 +3,24 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
+/// Pages the swap device holds, 64 MiB.
+pub const SWAP_PAGES: usize = 16384;
+/// Frames a fork leaves free for the child. Until it has pages of its own,
+/// all its pages are shared with the parent and cannot be swapped out.
+pub const FORK_SPARE_FRAMES: usize = 64;
+/// Disk blocks kept in the kernel heap, 512 KiB.
+pub const BLOCK_CACHE_CAPACITY: usize = 1024;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
+/// The user stacks of the threads lie side by side from here on, the
+/// heap below may grow up to it.
+pub const USER_STACK_BASE: usize = 0x10_0000_0000;
+/// Where `sys_mmap` places mappings without a usable address hint,
+/// up to the end of the lower half of the sv39 address space.
+pub const MMAP_BASE: usize = 0x20_0000_0000;
+pub const MMAP_END: usize = 0x40_0000_0000;
+
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
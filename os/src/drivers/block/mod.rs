@@ -2,13 +2,17 @@ mod virtio_blk;
 
 pub use virtio_blk::VirtIOBlock;
 
-use crate::board::BlockDeviceImpl;
+use crate::board::{BlockDeviceImpl, VIRTIO_SWAP};
 use alloc::sync::Arc;
 use easy_fs::BlockDevice;
 use lazy_static::*;
 
 lazy_static! {
     pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// Holds the user pages swapped out of memory. It is used in the
+    /// middle of page faults and so always polled.
+    pub static ref SWAP_DEVICE: Arc<dyn BlockDevice> =
+        Arc::new(VirtIOBlock::with_header(VIRTIO_SWAP, true));
 }
 
 #[allow(unused)]
@@ -12,11 +12,13 @@ const VIRTIO0: usize = 0x10008000;
 pub struct VirtIOBlock {
     virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
     condvars: BTreeMap<u16, Condvar>,
+    /// Never wait for an interrupt but spin until a request is done.
+    polling: bool,
 }
 
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = !self.polling && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
             let mut resp = BlkResp::default();
             let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
@@ -37,7 +39,7 @@ impl BlockDevice for VirtIOBlock {
         }
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let nb = !self.polling && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
         if nb {
             let mut resp = BlkResp::default();
             let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
@@ -68,9 +70,14 @@ impl BlockDevice for VirtIOBlock {
 
 impl VirtIOBlock {
     pub fn new() -> Self {
+        Self::with_header(VIRTIO0, false)
+    }
+    /// The device whose registers are at `base`, spinning on its requests
+    /// if `polling`.
+    pub fn with_header(base: usize, polling: bool) -> Self {
         let virtio_blk = unsafe {
             UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
+                VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).unwrap(),
             )
         };
         let mut condvars = BTreeMap::new();
@@ -82,6 +89,7 @@ impl VirtIOBlock {
         Self {
             virtio_blk,
             condvars,
+            polling,
         }
     }
 }
@@ -6,7 +6,7 @@ pub mod input;
 pub mod net;
 pub mod plic;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, SWAP_DEVICE};
 pub use bus::*;
 pub use gpu::*;
 pub use input::*;
@@ -1,11 +1,17 @@
-use super::File;
+use super::{File, SeekFrom, Stat, StatMode};
+use crate::config::BLOCK_CACHE_CAPACITY;
 use crate::drivers::BLOCK_DEVICE;
 use crate::mm::UserBuffer;
 use crate::sync::UPIntrFreeCell;
+use crate::timer::get_time_ms;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
+use easy_fs::{
+    block_cache_set_capacity, block_cache_sync_all, DiskInodeType, EasyFileSystem, Inode,
+    MAX_FILE_SIZE,
+};
 use lazy_static::*;
 
 pub struct OSInode {
@@ -45,11 +51,18 @@ impl OSInode {
 
 lazy_static! {
     pub static ref ROOT_INODE: Arc<Inode> = {
+        block_cache_set_capacity(BLOCK_CACHE_CAPACITY);
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
+        efs.lock().set_clock(|| get_time_ms() as u64);
+        EasyFileSystem::root_inode(&efs)
     };
 }
 
+/// Write everything changed so far back to the disk.
+pub fn sync_all() {
+    block_cache_sync_all();
+}
+
 pub fn list_apps() {
     println!("/**** APPS ****");
     for app in ROOT_INODE.ls() {
@@ -82,29 +95,191 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// How many symbolic links a single path lookup may follow before it is
+/// considered a loop.
+const MAX_SYMLINK_FOLLOWS: usize = 8;
+
+/// Walk `path` one component at a time, starting from the root for absolute
+/// paths and from the working directory `cwd` otherwise.
+///
+/// Symbolic links are followed in every component but the last, which is
+/// only followed if `follow_last` is set.
+fn find_inode(cwd: &str, path: &str, follow_last: bool) -> Option<Arc<Inode>> {
+    let mut follows = MAX_SYMLINK_FOLLOWS;
+    let start = lookup(ROOT_INODE.clone(), cwd, true, &mut follows)?;
+    lookup(start, path, follow_last, &mut follows)
+}
+
+fn lookup(
+    start: Arc<Inode>,
+    path: &str,
+    follow_last: bool,
+    follows: &mut usize,
+) -> Option<Arc<Inode>> {
+    let mut inode = if path.starts_with('/') {
+        ROOT_INODE.clone()
+    } else {
+        start
+    };
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+    while let Some(name) = names.next() {
+        let next = inode.find(name)?;
+        if next.is_symlink() && (follow_last || names.peek().is_some()) {
+            if *follows == 0 {
+                return None;
+            }
+            *follows -= 1;
+            // a relative target is relative to the directory holding the link
+            let target = next.read_link()?;
+            inode = lookup(inode, target.as_str(), true, follows)?;
+        } else {
+            inode = next;
+        }
+    }
+    Some(inode)
+}
+
+/// Split `path` into the directory holding its last component and that component.
+fn find_parent<'a>(cwd: &str, path: &'a str) -> Option<(Arc<Inode>, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = match path.rfind('/') {
+        Some(idx) => (&path[..=idx], &path[idx + 1..]),
+        None => ("", path),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let parent = find_inode(cwd, dir, true)?;
+    if !parent.is_dir() {
+        return None;
+    }
+    Some((parent, name))
+}
+
+/// The absolute path of the directory `dir`, found by walking ".." up to
+/// the root and looking for each directory's name in its parent. Paths
+/// through symbolic links thus come out as `lookup` resolved them.
+fn dir_path(dir: Arc<Inode>) -> Option<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut inode = dir;
+    loop {
+        let parent = inode.find("..")?;
+        // the root is its own parent
+        if parent.inode_id() == inode.inode_id() {
+            break;
+        }
+        let mut offset = 0;
+        let name = loop {
+            let entry = parent.read_dir(offset)?;
+            if entry.inode_id == inode.inode_id() && entry.name != "." && entry.name != ".." {
+                break entry.name;
+            }
+            offset = entry.next_offset;
+        };
+        names.push(name);
+        inode = parent;
+    }
+    names.reverse();
+    let mut path = String::from("/");
+    path.push_str(&names.join("/"));
+    Some(path)
+}
+
+pub fn open_file(cwd: &str, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = find_inode(cwd, path, true) {
+            if inode.is_dir() {
+                return None;
+            }
             // clear size
             inode.clear();
             Some(Arc::new(OSInode::new(readable, writable, inode)))
         } else {
             // create file
-            ROOT_INODE
+            let (parent, name) = find_parent(cwd, path)?;
+            parent
                 .create(name)
                 .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
         }
     } else {
-        ROOT_INODE.find(name).map(|inode| {
+        find_inode(cwd, path, true).and_then(|inode| {
+            if inode.is_dir() && writable {
+                return None;
+            }
             if flags.contains(OpenFlags::TRUNC) {
                 inode.clear();
             }
-            Arc::new(OSInode::new(readable, writable, inode))
+            Some(Arc::new(OSInode::new(readable, writable, inode)))
         })
     }
 }
 
+pub fn mkdir(cwd: &str, path: &str) -> bool {
+    find_parent(cwd, path)
+        .and_then(|(parent, name)| parent.create_dir(name))
+        .is_some()
+}
+
+pub fn rmdir(cwd: &str, path: &str) -> bool {
+    find_parent(cwd, path).map_or(false, |(parent, name)| parent.remove_dir(name))
+}
+
+/// Give the file at `old_path` the additional name `new_path`.
+pub fn link(cwd: &str, old_path: &str, new_path: &str) -> bool {
+    let inode = match find_inode(cwd, old_path, false) {
+        Some(inode) => inode,
+        None => return false,
+    };
+    find_parent(cwd, new_path).map_or(false, |(parent, name)| parent.link(name, &inode))
+}
+
+/// Move the entry at `old_path` to `new_path`, replacing what is there
+/// unless `no_replace` is set.
+pub fn rename(cwd: &str, old_path: &str, new_path: &str, no_replace: bool) -> bool {
+    let (old_parent, old_name) = match find_parent(cwd, old_path) {
+        Some(parent) => parent,
+        None => return false,
+    };
+    let (new_parent, new_name) = match find_parent(cwd, new_path) {
+        Some(parent) => parent,
+        None => return false,
+    };
+    if no_replace && new_parent.find(new_name).is_some() {
+        return false;
+    }
+    old_parent.rename(old_name, &new_parent, new_name)
+}
+
+pub fn unlink(cwd: &str, path: &str) -> bool {
+    find_parent(cwd, path).map_or(false, |(parent, name)| parent.unlink(name))
+}
+
+/// Create a symbolic link at `path` pointing at `target`, which is stored as is.
+pub fn symlink(cwd: &str, target: &str, path: &str) -> bool {
+    find_parent(cwd, path)
+        .and_then(|(parent, name)| parent.create_symlink(name, target))
+        .is_some()
+}
+
+/// Return the target of the symbolic link at `path`.
+pub fn readlink(cwd: &str, path: &str) -> Option<String> {
+    find_inode(cwd, path, false).and_then(|inode| inode.read_link())
+}
+
+/// Return the absolute path of the new working directory if `path` names
+/// a directory.
+pub fn chdir(cwd: &str, path: &str) -> Option<String> {
+    find_inode(cwd, path, true)
+        .filter(|inode| inode.is_dir())
+        .and_then(dir_path)
+}
+
+/// `d_type` values of the records filled in by `getdents`.
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
 impl File for OSInode {
     fn readable(&self) -> bool {
         self.readable
@@ -112,28 +287,136 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
+        let read_size = read_inode_at(&inner.inode, inner.offset, buf);
+        inner.offset += read_size;
+        read_size
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let write_size = write_inode_at(&inner.inode, inner.offset, buf);
+        inner.offset += write_size;
+        write_size
+    }
+    fn stat(&self) -> Stat {
+        let metadata = self.inner.exclusive_access().inode.metadata();
+        Stat {
+            dev: 0,
+            ino: metadata.inode_id as u64,
+            mode: match metadata.type_ {
+                DiskInodeType::File => StatMode::FILE,
+                DiskInodeType::Directory => StatMode::DIR,
+                DiskInodeType::Symlink => StatMode::LINK,
+            },
+            nlink: metadata.nlink,
+            size: metadata.size as u64,
+            mtime: metadata.mtime,
+            ctime: metadata.ctime,
+        }
+    }
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => inner.offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => {
+                (inner.inode.metadata().size as usize).checked_add_signed(delta)
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
+        }?;
+        // no file grows past there
+        if offset > MAX_FILE_SIZE {
+            return None;
         }
-        total_read_size
+        inner.offset = offset;
+        Some(offset)
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        Some(read_inode_at(&inner.inode, offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        match offset.checked_add(buf.len()) {
+            Some(end) if end <= MAX_FILE_SIZE => {}
+            _ => return None,
+        }
+        let inner = self.inner.exclusive_access();
+        Some(write_inode_at(&inner.inode, offset, buf))
+    }
+    fn truncate(&self, new_size: usize) -> bool {
+        if new_size > MAX_FILE_SIZE {
+            return false;
+        }
+        self.inner
+            .exclusive_access()
+            .inode
+            .truncate(new_size as u32)
+    }
+    fn getdents(&self, mut buf: UserBuffer) -> Option<usize> {
         let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+        if !inner.inode.is_dir() {
+            return None;
+        }
+        let mut records: Vec<u8> = Vec::new();
+        while let Some(entry) = inner.inode.read_dir(inner.offset) {
+            // d_ino, d_off, d_reclen and d_type, then the name and its `\0`
+            let reclen = (19 + entry.name.len() + 1 + 7) & !7;
+            if records.len() + reclen > buf.len() {
+                break;
+            }
+            records.extend_from_slice(&(entry.inode_id as u64).to_le_bytes());
+            records.extend_from_slice(&(entry.next_offset as i64).to_le_bytes());
+            records.extend_from_slice(&(reclen as u16).to_le_bytes());
+            records.push(match entry.type_ {
+                DiskInodeType::File => DT_REG,
+                DiskInodeType::Directory => DT_DIR,
+                DiskInodeType::Symlink => DT_LNK,
+            });
+            records.extend_from_slice(entry.name.as_bytes());
+            records.resize(records.len() + reclen - 19 - entry.name.len(), 0);
+            inner.offset = entry.next_offset;
+        }
+        if records.is_empty() && inner.inode.read_dir(inner.offset).is_some() {
+            // not even one record fits
+            return None;
+        }
+        let mut rest = records.as_slice();
+        for slice in buf.buffers.iter_mut() {
+            let len = rest.len().min(slice.len());
+            slice[..len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+        }
+        Some(records.len())
+    }
+    fn sync(&self, data_only: bool) -> bool {
+        self.inner.exclusive_access().inode.sync(data_only);
+        true
+    }
+}
+
+fn read_inode_at(inode: &Inode, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, *slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    total_read_size
+}
+
+fn write_inode_at(inode: &Inode, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = inode.write_at(offset, *slice);
+        offset += write_size;
+        total_write_size += write_size;
+        // the file cannot grow any further
+        if write_size < slice.len() {
+            break;
         }
-        total_write_size
     }
+    total_write_size
 }
@@ -3,14 +3,102 @@ mod pipe;
 mod stdio;
 
 use crate::mm::UserBuffer;
+use bitflags::*;
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    fn stat(&self) -> Stat;
+    /// Move the offset used by `read`/`write` and return the new offset,
+    /// or `None` if the file is not seekable or the result would be negative.
+    fn seek(&self, _pos: SeekFrom) -> Option<usize> {
+        None
+    }
+    /// Read from `offset` without touching the file offset.
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write at `offset` without touching the file offset.
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Cut or extend the file to `new_size` bytes; `false` if not supported.
+    fn truncate(&self, _new_size: usize) -> bool {
+        false
+    }
+    /// Make what was written durable, along with the metadata unless
+    /// `data_only`; `false` if not supported.
+    fn sync(&self, _data_only: bool) -> bool {
+        false
+    }
+    /// Fill `buf` with `linux_dirent64` records and move the offset past them.
+    /// Return `None` if this is not a directory or not even one record fits.
+    fn getdents(&self, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
 }
 
-pub use inode::{list_apps, open_file, OpenFlags};
+/// The position argument of `File::seek`.
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// The metadata of a file as returned by `sys_fstat`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// ID of the device containing the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type
+    pub mode: StatMode,
+    /// number of hard links
+    pub nlink: u32,
+    /// size in bytes
+    pub size: u64,
+    /// last modification of the contents, in ms since boot
+    pub mtime: u64,
+    /// last change of the contents or the metadata, in ms since boot
+    pub ctime: u64,
+}
+
+impl Stat {
+    /// Metadata of a file that is not backed by an inode.
+    pub fn without_inode(mode: StatMode) -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode,
+            nlink: 1,
+            size: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+bitflags! {
+    /// The file type bits of `Stat::mode`.
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+        const LINK = 0o120000;
+        const SOCK = 0o140000;
+    }
+}
+
+pub use inode::{
+    chdir, link, list_apps, mkdir, open_file, readlink, rename, rmdir, symlink, sync_all, unlink,
+    OpenFlags,
+};
 pub use pipe::make_pipe;
 pub use stdio::{Stdin, Stdout};
@@ -1,4 +1,4 @@
-use super::File;
+use super::{File, Stat, StatMode};
 use crate::drivers::chardev::CharDevice;
 use crate::drivers::chardev::UART;
 use crate::mm::UserBuffer;
@@ -25,6 +25,9 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::CHR)
+    }
 }
 
 impl File for Stdout {
@@ -43,4 +46,7 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::CHR)
+    }
 }
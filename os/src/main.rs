@@ -4,7 +4,7 @@
 #![feature(alloc_error_handler)]
 
 //use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, INPUT_CONDVAR};
-use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, SWAP_DEVICE};
 extern crate alloc;
 
 #[macro_use]
@@ -63,6 +63,9 @@ pub fn rust_main() -> ! {
     let _keyboard = KEYBOARD_DEVICE.clone();
     println!("KERN: init mouse");
     let _mouse = MOUSE_DEVICE.clone();
+    // its queue takes frames, which must not happen once memory runs out
+    println!("KERN: init swap");
+    let _swap = SWAP_DEVICE.clone();
     println!("KERN: init trap");
     trap::init();
     trap::enable_timer_interrupt();
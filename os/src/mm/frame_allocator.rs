@@ -1,6 +1,7 @@
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
 use crate::sync::UPIntrFreeCell;
+use crate::task::reclaim_frame;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -51,6 +52,9 @@ impl StackFrameAllocator {
         self.end = r.0;
         // println!("last {} Physical Frames.", self.end - self.current);
     }
+    pub fn free_count(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
@@ -109,10 +113,21 @@ pub fn init_frame_allocator() {
 }
 
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    loop {
+        let ppn = FRAME_ALLOCATOR.exclusive_access().alloc();
+        if let Some(ppn) = ppn {
+            return Some(FrameTracker::new(ppn));
+        }
+        // out of memory, make room by swapping out a page
+        if !reclaim_frame() {
+            return None;
+        }
+    }
+}
+
+/// How many frames can be allocated without swapping anything out.
+pub fn frames_free() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free_count()
 }
 
 pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
@@ -1,13 +1,18 @@
-use super::{frame_alloc, FrameTracker};
+use super::swap::SwapSlot;
+use super::{frame_alloc, frames_free, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
+use crate::config::{
+    MEMORY_END, MMAP_BASE, MMAP_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_STACK_BASE,
+};
 use crate::sync::UPIntrFreeCell;
+use crate::task::reclaim_frame;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::ops::RangeBounds;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -35,7 +40,13 @@ pub fn kernel_token() -> usize {
 
 pub struct MemorySet {
     page_table: PageTable,
+    /// Kept in address order.
     areas: Vec<MapArea>,
+    /// The heap covers `[heap_bottom, brk)`, right after the elf.
+    heap_bottom: usize,
+    brk: usize,
+    /// Where `swap_out` goes on looking for a page to evict.
+    clock_hand: VirtPageNum,
 }
 
 impl MemorySet {
@@ -43,6 +54,9 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
+            clock_hand: VirtPageNum(0),
         }
     }
     pub fn token(&self) -> usize {
@@ -71,15 +85,181 @@ impl MemorySet {
             self.areas.remove(idx);
         }
     }
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the end of the heap to `new_brk`. Fails and leaves the heap
+    /// as is if that is below its start or runs into another mapping.
+    pub fn set_brk(&mut self, new_brk: usize) -> bool {
+        // keep a guard page below the stacks
+        if new_brk < self.heap_bottom || new_brk > USER_STACK_BASE - PAGE_SIZE {
+            return false;
+        }
+        let heap_start = VirtAddr::from(self.heap_bottom).floor();
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if new_end < old_end {
+            self.remove_range(new_end, old_end);
+        } else if new_end > old_end {
+            if self.areas.iter().any(|area| {
+                area.vpn_range.get_start() < new_end && old_end < area.vpn_range.get_end()
+            }) {
+                return false;
+            }
+            // pieces of the heap may have been unmapped, grow the last one
+            match self.areas.iter_mut().find(|area| {
+                area.vpn_range.get_end() == old_end && area.vpn_range.get_start() >= heap_start
+            }) {
+                Some(area) => area.grow_to(new_end),
+                None => self.push(
+                    MapArea::new(
+                        old_end.into(),
+                        new_end.into(),
+                        MapType::Framed,
+                        MapPermission::R | MapPermission::W | MapPermission::U,
+                    ),
+                    None,
+                ),
+            }
+        }
+        self.brk = new_brk;
+        true
+    }
+    /// Find `len` bytes of free address space for a new mapping, at `hint`
+    /// if that is free, and return their start.
+    pub fn find_free_area(&self, hint: VirtAddr, len: usize) -> Option<VirtAddr> {
+        let pages = VirtAddr::from(len).ceil().0;
+        let is_free = |start: VirtPageNum| {
+            let end = VirtPageNum(start.0 + pages);
+            end <= VirtAddr::from(MMAP_END).floor()
+                && self.areas.iter().all(|area| {
+                    end <= area.vpn_range.get_start() || area.vpn_range.get_end() <= start
+                })
+        };
+        let base = VirtAddr::from(MMAP_BASE).floor();
+        if hint.aligned() && hint.floor() >= base && is_free(hint.floor()) {
+            return Some(hint);
+        }
+        // first fit: right at the base or right after some area
+        let mut candidates: Vec<VirtPageNum> = self
+            .areas
+            .iter()
+            .map(|area| area.vpn_range.get_end())
+            .filter(|&end| end > base)
+            .collect();
+        candidates.push(base);
+        candidates.sort();
+        candidates
+            .into_iter()
+            .find(|&start| is_free(start))
+            .map(VirtAddr::from)
+    }
+    /// Unmap every page in `[start_vpn, end_vpn)`, cutting areas which
+    /// reach beyond the range. Fails if the user may not touch some of it.
+    pub fn remove_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        match self.split_range(start_vpn, end_vpn) {
+            Some(starts) => {
+                for start in starts {
+                    self.remove_area_with_start_vpn(start);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    /// Give every page in `[start_vpn, end_vpn)` the permission `map_perm`,
+    /// cutting areas which reach beyond the range. Fails if the user may
+    /// not touch some of it.
+    pub fn protect_range(
+        &mut self,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+        map_perm: MapPermission,
+    ) -> bool {
+        match self.split_range(start_vpn, end_vpn) {
+            Some(starts) => {
+                for area in self
+                    .areas
+                    .iter_mut()
+                    .filter(|area| starts.contains(&area.vpn_range.get_start()))
+                {
+                    area.set_perm(&mut self.page_table, map_perm);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    /// Cut the areas overlapping `[start_vpn, end_vpn)` at its bounds and
+    /// return the starts of those inside, or `None` if one of them is not
+    /// accessible to the user.
+    fn split_range(
+        &mut self,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+    ) -> Option<Vec<VirtPageNum>> {
+        let overlaps = |area: &MapArea| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        };
+        if self
+            .areas
+            .iter()
+            .any(|area| overlaps(area) && !area.map_perm.contains(MapPermission::U))
+        {
+            return None;
+        }
+        for vpn in [start_vpn, end_vpn] {
+            if let Some(idx) = self
+                .areas
+                .iter()
+                .position(|area| area.vpn_range.get_start() < vpn && vpn < area.vpn_range.get_end())
+            {
+                let tail = self.areas[idx].split_off(vpn);
+                self.areas.insert(idx + 1, tail);
+            }
+        }
+        Some(
+            self.areas
+                .iter()
+                .filter(|area| overlaps(area))
+                .map(|area| area.vpn_range.get_start())
+                .collect(),
+        )
+    }
     /// Add a new MapArea into this MemorySet.
     /// Assuming that there are no conflicts in the virtual address
     /// space.
     pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
-            map_area.copy_data(&self.page_table, data);
+            map_area.copy_data(&mut self.page_table, data);
+        }
+        self.insert_area(map_area);
+    }
+    /// Put `map_area` among the others in address order and return its index.
+    fn insert_area(&mut self, map_area: MapArea) -> usize {
+        let start_vpn = map_area.vpn_range.get_start();
+        let idx = self
+            .areas
+            .partition_point(|area| area.vpn_range.get_start() < start_vpn);
+        self.areas.insert(idx, map_area);
+        idx
+    }
+    /// Push the lazy `map_area` with its first pages backed by `frames`,
+    /// false if there is no memory left for the page table.
+    pub fn push_with_frames(&mut self, map_area: MapArea, frames: Vec<FrameTracker>) -> bool {
+        assert!(map_area.is_lazy());
+        let start_vpn = map_area.vpn_range.get_start();
+        let idx = self.insert_area(map_area);
+        for (i, frame) in frames.into_iter().enumerate() {
+            let vpn = VirtPageNum(start_vpn.0 + i);
+            if !self.prepare_table(vpn) {
+                self.areas.remove(idx).unmap(&mut self.page_table);
+                return false;
+            }
+            self.areas[idx].map_frame(&mut self.page_table, vpn, frame);
         }
-        self.areas.push(map_area);
+        true
     }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
@@ -166,7 +346,7 @@ impl MemorySet {
         }
         memory_set
     }
-    /// Include sections in elf and trampoline,
+    /// Include sections in elf, an empty heap after them and trampoline,
     /// also returns user_sp_base and entry point.
     pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
         let mut memory_set = Self::new_bare();
@@ -204,21 +384,52 @@ impl MemorySet {
             }
         }
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE;
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.brk = memory_set.heap_bottom;
+        memory_set.push(
+            MapArea::new(
+                max_end_va,
+                max_end_va,
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        );
         (
             memory_set,
-            user_stack_base,
+            USER_STACK_BASE,
             elf.header.pt2.entry_point() as usize,
         )
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+    /// Clone `user_space` for a forked child. The pages the user can
+    /// access are shared copy-on-write: both sides map them read-only,
+    /// and the first write to one copies it, see `handle_page_fault`.
+    /// The rest, trap contexts included, is copied at once.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
         // map trampoline
         memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Framed && area.map_perm.contains(MapPermission::U) {
+                let flags = area.pte_flags() - PTEFlags::W;
+                for (&vpn, frame) in area.data_frames.iter() {
+                    // a page changed since it was swapped out is for the child too
+                    let accessed_dirty =
+                        user_space.translate(vpn).unwrap().flags() & (PTEFlags::A | PTEFlags::D);
+                    user_space.page_table.remap(vpn, frame.ppn, flags);
+                    memory_set
+                        .page_table
+                        .map(vpn, frame.ppn, flags | accessed_dirty);
+                    new_area.data_frames.insert(vpn, Arc::clone(frame));
+                }
+                new_area.swapped = area.swapped.clone();
+                memory_set.areas.push(new_area);
+                continue;
+            }
+            // copy the rest
             memory_set.push(new_area, None);
             // copy data from another space
             for vpn in area.vpn_range {
@@ -231,6 +442,29 @@ impl MemorySet {
         }
         memory_set
     }
+    /// At most how many frames `from_existed_user` takes to clone this
+    /// space: a page table as large as this one, and copies of the pages
+    /// which are not shared.
+    pub fn fork_frames(&self) -> usize {
+        let copied: usize = self
+            .areas
+            .iter()
+            .filter(|area| area.map_type == MapType::Framed && !area.is_lazy())
+            .map(|area| area.data_frames.len())
+            .sum();
+        self.page_table.frame_count() + copied
+    }
+    /// Swap out pages until `count` frames are free, for allocations made
+    /// while this space is locked, which `reclaim_frame` passes over.
+    /// False if not that many pages can be given up.
+    pub fn reserve_frames(&mut self, count: usize) -> bool {
+        while frames_free() < count {
+            if !reclaim_frame() && !self.swap_out() {
+                return false;
+            }
+        }
+        true
+    }
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -241,15 +475,167 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Resolve a page fault at `vpn`, caused by a write if `write`, and
+    /// return whether the access may be retried. An absent page of a lazy
+    /// area gets a zeroed frame, or its contents back from the swap device,
+    /// and a write to a page shared copy-on-write gives the page a frame
+    /// of its own. Fails when out of both memory and swap too.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, write: bool) -> bool {
+        let idx = match self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.contains(vpn))
+        {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let pte = match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => pte,
+            _ if self.areas[idx].is_lazy() => {
+                if !self.prepare_table(vpn) {
+                    return false;
+                }
+                let frame = match self.alloc_frame() {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                self.areas[idx].swap_in(&mut self.page_table, vpn, frame);
+                return true;
+            }
+            _ => return false,
+        };
+        if !write || pte.writable() || !self.areas[idx].is_copy_on_write() {
+            return false;
+        }
+        let shared = self.areas[idx]
+            .data_frames
+            .get(&vpn)
+            .map_or(false, |frame| Arc::strong_count(frame) > 1);
+        let copy = if shared {
+            // shared pages are never swapped out, so the page stays
+            match self.alloc_frame() {
+                Some(frame) => Some(frame),
+                None => return false,
+            }
+        } else {
+            None
+        };
+        self.areas[idx].copy_on_write(&mut self.page_table, vpn, copy);
+        true
+    }
+    /// The frame behind `vpn` for the kernel to read, or write if `write`,
+    /// `None` if the user may not do so itself. The kernel goes through the
+    /// frame and so takes no page fault, thus the fault is handled here
+    /// first. The frame is not swapped out while the returned reference is
+    /// held.
+    pub fn user_frame(&mut self, vpn: VirtPageNum, write: bool) -> Option<Arc<FrameTracker>> {
+        let perm = self
+            .areas
+            .iter()
+            .find(|area| area.vpn_range.contains(vpn))?
+            .map_perm;
+        // a read-only frame may well be shared with another process
+        if !perm.contains(MapPermission::U) || (write && !perm.contains(MapPermission::W)) {
+            return None;
+        }
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && (pte.writable() || !write) => {}
+            _ => {
+                if !self.handle_page_fault(vpn, write) {
+                    return None;
+                }
+            }
+        }
+        if write {
+            self.page_table.mark_dirty(vpn);
+        }
+        self.areas
+            .iter()
+            .find(|area| area.vpn_range.contains(vpn))?
+            .data_frames
+            .get(&vpn)
+            .cloned()
+    }
+    /// Make sure the page table has the tables on the way to `vpn`, with
+    /// frames from `alloc_frame`.
+    fn prepare_table(&mut self, vpn: VirtPageNum) -> bool {
+        while self.page_table.translate(vpn).is_none() {
+            match self.alloc_frame() {
+                Some(frame) => self.page_table.add_table(vpn, frame),
+                None => return false,
+            }
+        }
+        true
+    }
+    /// A frame for a page of this space, swapping out one of its own pages
+    /// when the other spaces have none to spare.
+    pub fn alloc_frame(&mut self) -> Option<FrameTracker> {
+        loop {
+            if let Some(frame) = frame_alloc() {
+                return Some(frame);
+            }
+            if !self.swap_out() {
+                return None;
+            }
+        }
+    }
+    /// Move a page of the lazy areas out to the swap device and return
+    /// whether there was one to spare. This is the clock algorithm: pages
+    /// are visited in address order from where the last call stopped, and
+    /// one accessed since the last visit gets a second chance. Pages shared
+    /// with other spaces or pinned by the kernel are left alone.
+    pub fn swap_out(&mut self) -> bool {
+        let hand = self.clock_hand;
+        let round =
+            || evictable_pages(&self.areas, hand..).chain(evictable_pages(&self.areas, ..hand));
+        // every accessed bit is clear by the second round
+        let victim = round()
+            .chain(round())
+            .find(|&(_, vpn)| !self.page_table.take_accessed(vpn));
+        let swapped_out = match victim {
+            Some((idx, vpn)) => {
+                self.clock_hand = VirtPageNum(vpn.0 + 1);
+                self.areas[idx].swap_out(&mut self.page_table, vpn)
+            }
+            None => false,
+        };
+        // forget the accessed bits and the mapping cached in the TLB
+        unsafe {
+            asm!("sfence.vma");
+        }
+        swapped_out
+    }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
     }
 }
 
+/// The pages of the lazy areas in `range` which may be swapped out, with
+/// the index of their area, in address order.
+fn evictable_pages<'a, R: RangeBounds<VirtPageNum> + Clone + 'a>(
+    areas: &'a [MapArea],
+    range: R,
+) -> impl Iterator<Item = (usize, VirtPageNum)> + 'a {
+    areas
+        .iter()
+        .enumerate()
+        .filter(|(_, area)| area.is_lazy())
+        .flat_map(move |(idx, area)| {
+            area.data_frames
+                .range(range.clone())
+                .filter(|(_, frame)| Arc::strong_count(frame) == 1)
+                .map(move |(&vpn, _)| (idx, vpn))
+        })
+}
+
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// Shared with other address spaces while copy-on-write after a fork.
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
+    /// Copies of the pages swapped out, kept after swapping one back in
+    /// until it is written to, then the copy is out of date.
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
     map_type: MapType,
     map_perm: MapPermission,
 }
@@ -266,6 +652,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type,
             map_perm,
         }
@@ -274,6 +661,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            swapped: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
@@ -287,7 +675,7 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
             MapType::Linear(pn_offset) => {
                 // check for sv39
@@ -295,16 +683,121 @@ impl MapArea {
                 ppn = PhysPageNum((vpn.0 as isize + pn_offset) as usize);
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, ppn, self.pte_flags());
+    }
+    fn pte_flags(&self) -> PTEFlags {
+        PTEFlags::from_bits(self.map_perm.bits).unwrap()
+    }
+    /// Move the pages from `vpn` on into an area of their own.
+    fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        let tail = Self {
+            vpn_range: VPNRange::new(vpn, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&vpn),
+            swapped: self.swapped.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+        tail
+    }
+    /// Move the end of a lazy area up to `end_vpn`, there is nothing to map.
+    fn grow_to(&mut self, end_vpn: VirtPageNum) {
+        assert!(self.is_lazy());
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), end_vpn);
+    }
+    /// Change the permission to `map_perm`, for the pages mapped already too.
+    fn set_perm(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        self.map_perm = map_perm;
+        let flags = self.pte_flags();
+        for vpn in self.vpn_range {
+            let pte = match page_table.translate(vpn) {
+                Some(pte) if pte.is_valid() => pte,
+                _ => continue,
+            };
+            // stay read-only while shared copy-on-write
+            let shared = self
+                .data_frames
+                .get(&vpn)
+                .map_or(false, |frame| Arc::strong_count(frame) > 1);
+            if shared {
+                page_table.remap(vpn, pte.ppn(), flags - PTEFlags::W);
+            } else {
+                page_table.remap(vpn, pte.ppn(), flags);
+            }
+        }
+    }
+    /// Whether frames are only allocated on the first touch of a page.
+    /// Framed areas the kernel uses by itself, kernel stacks and trap
+    /// contexts, are backed at once as the kernel cannot fault on them.
+    fn is_lazy(&self) -> bool {
+        self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::U)
+    }
+    /// Whether a read-only mapping in here is a page shared since a fork.
+    fn is_copy_on_write(&self) -> bool {
+        self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::W)
+    }
+    /// Map `vpn` writable again, copying its frame into `copy` first
+    /// unless nobody else shares it any more.
+    fn copy_on_write(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        copy: Option<FrameTracker>,
+    ) {
+        let frame = self.data_frames.get_mut(&vpn).unwrap();
+        if Arc::strong_count(frame) > 1 {
+            let copy = copy.unwrap();
+            copy.ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            *frame = Arc::new(copy);
+        }
+        let ppn = frame.ppn;
+        page_table.remap(vpn, ppn, self.pte_flags());
+        // about to be written to
+        self.swapped.remove(&vpn);
+    }
+    /// Back the absent `vpn` with `frame`, holding its contents again if
+    /// it was swapped out.
+    fn swap_in(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, frame: FrameTracker) {
+        if let Some(slot) = self.swapped.get(&vpn) {
+            slot.read(frame.ppn);
+        }
+        self.map_frame(page_table, vpn, frame);
+    }
+    /// Back the absent `vpn` with `frame` as it is.
+    fn map_frame(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, frame: FrameTracker) {
+        page_table.map(vpn, frame.ppn, self.pte_flags());
+        self.data_frames.insert(vpn, Arc::new(frame));
+    }
+    /// Give up the frame of `vpn`, writing it to the swap device first
+    /// unless an up-to-date copy is there already. Fails if it is full.
+    fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let pte = page_table.translate(vpn).unwrap();
+        if pte.dirty() || !self.swapped.contains_key(&vpn) {
+            let slot = match SwapSlot::alloc() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            slot.write(pte.ppn());
+            self.swapped.insert(vpn, Arc::new(slot));
+        }
+        self.data_frames.remove(&vpn);
+        page_table.unmap(vpn);
+        true
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        self.swapped.remove(&vpn);
+        if self.map_type == MapType::Framed && self.data_frames.remove(&vpn).is_none() {
+            // never touched, or swapped out
+            return;
         }
         page_table.unmap(vpn);
     }
     pub fn map(&mut self, page_table: &mut PageTable) {
+        if self.is_lazy() {
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
@@ -316,12 +809,16 @@ impl MapArea {
     }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &PageTable, data: &[u8]) {
+    /// pages of lazy areas are backed here as far as data goes
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
         let mut start: usize = 0;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
         loop {
+            if !self.data_frames.contains_key(&current_vpn) {
+                self.map_one(page_table, current_vpn);
+            }
             let src = &data[start..len.min(start + PAGE_SIZE)];
             let dst = &mut page_table
                 .translate(current_vpn)
@@ -1,5 +1,7 @@
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use crate::task::current_process;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -50,6 +52,12 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -109,12 +117,48 @@ impl PageTable {
         }
         result
     }
+    /// Use `frame` for the first table missing on the way to `vpn`, for
+    /// callers which cannot assume that frames are there.
+    pub fn add_table(&mut self, vpn: VirtPageNum, frame: FrameTracker) {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        for idx in &idxs[..2] {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if !pte.is_valid() {
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+                return;
+            }
+            ppn = pte.ppn();
+        }
+        panic!("no table is missing for vpn {:?}", vpn);
+    }
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
         let pte = self.find_pte_create(vpn).unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
+    /// Point the mapping of `vpn` at `ppn` with `flags` instead, keeping
+    /// the accessed and dirty bits.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        let kept = pte.flags() & (PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(ppn, flags | kept | PTEFlags::V);
+    }
+    /// Clear the accessed bit of `vpn` and return whether it was set.
+    pub fn take_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = self.find_pte(vpn).unwrap();
+        let accessed = pte.accessed();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+        accessed
+    }
+    /// Set the dirty bit of `vpn`, for writes which bypass the MMU.
+    pub fn mark_dirty(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() | PTEFlags::D);
+    }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find_pte(vpn).unwrap();
@@ -135,17 +179,49 @@ impl PageTable {
     pub fn token(&self) -> usize {
         8usize << 60 | self.root_ppn.0
     }
+    /// The frames holding the tables.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
 }
 
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
+/// The frame behind `vpn` of the current user space, for the kernel to
+/// read, or write if `write`, see `MemorySet::user_frame`.
+fn user_frame(token: usize, vpn: VirtPageNum, write: bool) -> Option<Arc<FrameTracker>> {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    assert_eq!(
+        inner.memory_set.token(),
+        token,
+        "not the current user space"
+    );
+    inner.memory_set.user_frame(vpn, write)
+}
+
+fn user_addr(token: usize, va: VirtAddr, write: bool) -> Option<PhysAddr> {
+    let pa: PhysAddr = user_frame(token, va.floor(), write)?.ppn.into();
+    Some(PhysAddr::from(usize::from(pa) + va.page_offset()))
+}
+
+/// The user memory at `ptr`, for the kernel to read, or write if `write`,
+/// `None` if the user may not do so itself. The frames stay in memory for
+/// as long as the buffer lives.
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    write: bool,
+) -> Option<UserBuffer> {
     let mut start = ptr as usize;
-    let end = start + len;
+    let end = start.checked_add(len)?;
     let mut v = Vec::new();
+    let mut frames = Vec::new();
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let frame = user_frame(token, vpn, write)?;
+        let ppn = frame.ppn;
+        frames.push(frame);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -156,52 +232,47 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
         }
         start = end_va.into();
     }
-    v
+    Some(UserBuffer { buffers: v, frames })
 }
 
 /// Load a string from other address spaces into kernel space without an end `\0`.
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
+/// `None` if the user may not read all of it.
+pub fn translated_str(token: usize, ptr: *const u8) -> Option<String> {
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let ch: u8 = *(user_addr(token, VirtAddr::from(va), false)?.get_mut());
         if ch == 0 {
             break;
         }
         string.push(ch as char);
         va += 1;
     }
-    string
+    Some(string)
 }
 
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table
-        .translate_va(VirtAddr::from(ptr as usize))
-        .unwrap()
-        .get_ref()
+/// `None` if the user may not read at `ptr` itself.
+pub fn translated_ref<T>(token: usize, ptr: *const T) -> Option<&'static T> {
+    user_addr(token, VirtAddr::from(ptr as usize), false).map(|pa| pa.get_ref())
 }
 
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+/// `None` if the user may not write at `ptr` itself.
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> Option<&'static mut T> {
+    user_addr(token, VirtAddr::from(ptr as usize), true).map(|pa| pa.get_mut())
 }
 
 pub struct UserBuffer {
     pub buffers: Vec<&'static mut [u8]>,
+    /// Keep the user pages behind `buffers` from being swapped out.
+    frames: Vec<Arc<FrameTracker>>,
 }
 
 impl UserBuffer {
     pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
+        Self {
+            buffers,
+            frames: Vec::new(),
+        }
     }
     pub fn len(&self) -> usize {
         let mut total: usize = 0;
@@ -218,6 +289,7 @@ impl IntoIterator for UserBuffer {
     fn into_iter(self) -> Self::IntoIter {
         UserBufferIterator {
             buffers: self.buffers,
+            _frames: self.frames,
             current_buffer: 0,
             current_idx: 0,
         }
@@ -226,6 +298,7 @@ impl IntoIterator for UserBuffer {
 
 pub struct UserBufferIterator {
     buffers: Vec<&'static mut [u8]>,
+    _frames: Vec<Arc<FrameTracker>>,
     current_buffer: usize,
     current_idx: usize,
 }
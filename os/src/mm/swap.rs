@@ -0,0 +1,74 @@
+use super::PhysPageNum;
+use crate::config::{PAGE_SIZE, SWAP_PAGES};
+use crate::drivers::SWAP_DEVICE;
+use crate::sync::UPIntrFreeCell;
+use alloc::vec::Vec;
+use easy_fs::BLOCK_SZ;
+use lazy_static::*;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SZ;
+
+struct SlotAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl SlotAllocator {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == SWAP_PAGES {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, slot: usize) {
+        // validity check
+        if slot >= self.current || self.recycled.iter().any(|&v| v == slot) {
+            panic!("Swap slot {} has not been allocated!", slot);
+        }
+        self.recycled.push(slot);
+    }
+}
+
+lazy_static! {
+    static ref SLOT_ALLOCATOR: UPIntrFreeCell<SlotAllocator> = unsafe {
+        UPIntrFreeCell::new(SlotAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        })
+    };
+}
+
+/// A page on the swap device holding a copy of a user page, given back
+/// when dropped.
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    /// `None` if the swap device is full.
+    pub fn alloc() -> Option<Self> {
+        SLOT_ALLOCATOR.exclusive_access().alloc().map(Self)
+    }
+    /// Store the contents of the frame `ppn` here.
+    pub fn write(&self, ppn: PhysPageNum) {
+        let page = ppn.get_bytes_array();
+        for (i, block) in page.chunks(BLOCK_SZ).enumerate() {
+            SWAP_DEVICE.write_block(self.0 * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+    /// Load what is stored here into the frame `ppn`.
+    pub fn read(&self, ppn: PhysPageNum) {
+        let page = ppn.get_bytes_array();
+        for (i, block) in page.chunks_mut(BLOCK_SZ).enumerate() {
+            SWAP_DEVICE.read_block(self.0 * BLOCKS_PER_PAGE + i, block);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SLOT_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
@@ -2,7 +2,7 @@ use alloc::{sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
 use lose_net_stack::packets::tcp::TCPPacket;
 
-use crate::fs::File;
+use crate::fs::{File, Stat, StatMode};
 use crate::sync::UPIntrFreeCell;
 use crate::task::TaskControlBlock;
 
@@ -138,4 +138,8 @@ impl File for PortFd {
     fn write(&self, _buf: crate::mm::UserBuffer) -> usize {
         0
     }
+
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::SOCK)
+    }
 }
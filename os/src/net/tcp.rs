@@ -4,7 +4,10 @@ use lose_net_stack::IPv4;
 use lose_net_stack::MacAddress;
 use lose_net_stack::TcpFlags;
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::{
+    drivers::NET_DEVICE,
+    fs::{File, Stat, StatMode},
+};
 
 use super::socket::get_s_a_by_index;
 use super::{
@@ -104,6 +107,10 @@ impl File for TCP {
         NET_DEVICE.transmit(&tcp_packet.build_data());
         len
     }
+
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::SOCK)
+    }
 }
 
 impl Drop for TCP {
@@ -1,5 +1,8 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
+use crate::fs::{
+    chdir, link, make_pipe, mkdir, open_file, readlink, rename, rmdir, symlink, sync_all, unlink,
+    OpenFlags, SeekFrom, Stat,
+};
+use crate::mm::{translated_byte_buffer, translated_refmut, translated_str};
 use crate::task::{current_process, current_user_token};
 use alloc::sync::Arc;
 
@@ -17,7 +20,7 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        translated_byte_buffer(token, buf, len, false).map_or(-1, |buf| file.write(buf) as isize)
     } else {
         -1
     }
@@ -37,7 +40,152 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        translated_byte_buffer(token, buf, len, true).map_or(-1, |buf| file.read(buf) as isize)
+    } else {
+        -1
+    }
+}
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return -1,
+    };
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        file.seek(pos).map_or(-1, |offset| offset as isize)
+    } else {
+        -1
+    }
+}
+
+pub fn sys_pread(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        if !file.readable() {
+            return -1;
+        }
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        translated_byte_buffer(token, buf, len, true)
+            .and_then(|user_buf| file.read_at(offset, user_buf))
+            .map_or(-1, |size| size as isize)
+    } else {
+        -1
+    }
+}
+
+pub fn sys_pwrite(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.writable() {
+            return -1;
+        }
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        translated_byte_buffer(token, buf, len, false)
+            .and_then(|user_buf| file.write_at(offset, user_buf))
+            .map_or(-1, |size| size as isize)
+    } else {
+        -1
+    }
+}
+
+pub fn sys_ftruncate(fd: usize, length: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.writable() {
+            return -1;
+        }
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        if file.truncate(length) {
+            0
+        } else {
+            -1
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_sync() -> isize {
+    sync_all();
+    0
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    sync_fd(fd, false)
+}
+
+pub fn sys_fdatasync(fd: usize) -> isize {
+    sync_fd(fd, true)
+}
+
+fn sync_fd(fd: usize, data_only: bool) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        if file.sync(data_only) {
+            0
+        } else {
+            -1
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        translated_byte_buffer(token, buf, len, true)
+            .and_then(|user_buf| file.getdents(user_buf))
+            .map_or(-1, |size| size as isize)
     } else {
         -1
     }
@@ -46,8 +194,16 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(inode) = open_file(
+        cwd.as_str(),
+        path.as_str(),
+        OpenFlags::from_bits(flags).unwrap(),
+    ) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
@@ -79,8 +235,19 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    // writing to user space may need the PCB for copy-on-write
+    drop(inner);
+    let written = translated_refmut(token, pipe)
+        .map(|fd| *fd = read_fd)
+        .and_then(|_| translated_refmut(token, unsafe { pipe.add(1) }))
+        .map(|fd| *fd = write_fd)
+        .is_some();
+    if !written {
+        let mut inner = process.inner_exclusive_access();
+        inner.fd_table[read_fd].take();
+        inner.fd_table[write_fd].take();
+        return -1;
+    }
     0
 }
 
@@ -97,3 +264,212 @@ pub fn sys_dup(fd: usize) -> isize {
     inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
     new_fd as isize
 }
+
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        let stat = file.stat();
+        let stat_bytes = unsafe {
+            core::slice::from_raw_parts(
+                &stat as *const Stat as *const u8,
+                core::mem::size_of::<Stat>(),
+            )
+        };
+        // the struct may straddle a page boundary in user space
+        let user_buf = match translated_byte_buffer(
+            token,
+            st as *const u8,
+            core::mem::size_of::<Stat>(),
+            true,
+        ) {
+            Some(user_buf) => user_buf,
+            None => return -1,
+        };
+        for (byte_ref, byte) in user_buf.into_iter().zip(stat_bytes.iter()) {
+            unsafe {
+                *byte_ref = *byte;
+            }
+        }
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mkdir(path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if mkdir(cwd.as_str(), path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Remove a file, or an empty directory if `AT_REMOVEDIR` is given.
+pub fn sys_unlinkat(path: *const u8, flags: u32) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    let removed = if flags & AT_REMOVEDIR != 0 {
+        rmdir(cwd.as_str(), path.as_str())
+    } else {
+        unlink(cwd.as_str(), path.as_str())
+    };
+    if removed {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_linkat(old_path: *const u8, new_path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let old_path = match translated_str(token, old_path) {
+        Some(old_path) => old_path,
+        None => return -1,
+    };
+    let new_path = match translated_str(token, new_path) {
+        Some(new_path) => new_path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if link(cwd.as_str(), old_path.as_str(), new_path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Fail instead of replacing an existing `new_path`.
+const RENAME_NOREPLACE: u32 = 1;
+
+pub fn sys_renameat2(old_path: *const u8, new_path: *const u8, flags: u32) -> isize {
+    if flags & !RENAME_NOREPLACE != 0 {
+        return -1;
+    }
+    let process = current_process();
+    let token = current_user_token();
+    let old_path = match translated_str(token, old_path) {
+        Some(old_path) => old_path,
+        None => return -1,
+    };
+    let new_path = match translated_str(token, new_path) {
+        Some(new_path) => new_path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if rename(
+        cwd.as_str(),
+        old_path.as_str(),
+        new_path.as_str(),
+        flags & RENAME_NOREPLACE != 0,
+    ) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_symlinkat(target: *const u8, path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let target = match translated_str(token, target) {
+        Some(target) => target,
+        None => return -1,
+    };
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if symlink(cwd.as_str(), target.as_str(), path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copy the target of a symbolic link into `buf` without a trailing `\0`.
+/// Return the number of bytes copied.
+pub fn sys_readlinkat(path: *const u8, buf: *mut u8, len: usize) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(target) = readlink(cwd.as_str(), path.as_str()) {
+        let len = len.min(target.len());
+        let user_buf = match translated_byte_buffer(token, buf, len, true) {
+            Some(user_buf) => user_buf,
+            None => return -1,
+        };
+        for (byte_ref, byte) in user_buf.into_iter().zip(target.bytes()) {
+            unsafe {
+                *byte_ref = byte;
+            }
+        }
+        len as isize
+    } else {
+        -1
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(cwd) = chdir(cwd.as_str(), path.as_str()) {
+        process.inner_exclusive_access().cwd = cwd;
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copy the working directory with a trailing `\0` into `buf`.
+/// Return its length, or -1 if `buf` is too small.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if cwd.len() + 1 > len {
+        return -1;
+    }
+    let user_buf = match translated_byte_buffer(token, buf, cwd.len() + 1, true) {
+        Some(user_buf) => user_buf,
+        None => return -1,
+    };
+    for (byte_ref, byte) in user_buf.into_iter().zip(cwd.bytes().chain(Some(0))) {
+        unsafe {
+            *byte_ref = byte;
+        }
+    }
+    cwd.len() as isize
+}
@@ -0,0 +1,202 @@
+use crate::config::{MMAP_END, PAGE_SIZE};
+use crate::fs::{File, StatMode};
+use crate::mm::{FrameTracker, MapArea, MapPermission, MapType, UserBuffer, VirtAddr};
+use crate::task::current_process;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bitflags::*;
+
+bitflags! {
+    pub struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// The permission of pages mapped with `prot`, or `None` for `PROT_NONE`
+/// as a PTE without any of R/W/X would point to a next level table.
+fn map_permission(prot: usize) -> Option<MapPermission> {
+    let prot = MmapProt::from_bits(prot)?;
+    if prot.is_empty() {
+        return None;
+    }
+    let mut map_perm = MapPermission::U;
+    // riscv has no write-only pages
+    if prot.intersects(MmapProt::READ | MmapProt::WRITE) {
+        map_perm |= MapPermission::R;
+    }
+    if prot.contains(MmapProt::WRITE) {
+        map_perm |= MapPermission::W;
+    }
+    if prot.contains(MmapProt::EXEC) {
+        map_perm |= MapPermission::X;
+    }
+    Some(map_perm)
+}
+
+/// Read up to `len` bytes of `file` from `offset` into frames of the
+/// current process, a page at a time and fewer at the end of the file.
+/// `None` if out of memory or the file cannot be read.
+fn read_file(
+    file: &Arc<dyn File + Send + Sync>,
+    offset: usize,
+    len: usize,
+) -> Option<Vec<FrameTracker>> {
+    let process = current_process();
+    let mut frames = Vec::new();
+    let mut done = 0;
+    while done < len {
+        // the lock must not be held while the file is read
+        let frame = process.inner_exclusive_access().memory_set.alloc_frame()?;
+        let want = (len - done).min(PAGE_SIZE);
+        let slice = &mut frame.ppn.get_bytes_array()[..want];
+        let read = file.read_at(offset + done, UserBuffer::new(vec![slice]))?;
+        if read == 0 {
+            break;
+        }
+        frames.push(frame);
+        done += read;
+        if read < want {
+            break;
+        }
+    }
+    Some(frames)
+}
+
+/// Map `len` bytes, anonymous or from `fd` at `offset`, and return where.
+/// Only private mappings are supported, whose changes never reach the file.
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    if len == 0 || len > MMAP_END || offset % PAGE_SIZE != 0 {
+        return -1;
+    }
+    let (map_perm, flags) = match (map_permission(prot), MmapFlags::from_bits(flags)) {
+        (Some(map_perm), Some(flags)) => (map_perm, flags),
+        _ => return -1,
+    };
+    if flags.contains(MmapFlags::SHARED) || !flags.contains(MmapFlags::PRIVATE) {
+        return -1;
+    }
+    let process = current_process();
+    let frames = if flags.contains(MmapFlags::ANONYMOUS) {
+        Vec::new()
+    } else {
+        let inner = process.inner_exclusive_access();
+        let file = match inner.fd_table.get(fd) {
+            Some(Some(file)) => file.clone(),
+            _ => return -1,
+        };
+        // release current task TCB manually to avoid multi-borrow
+        drop(inner);
+        if !file.readable() || file.stat().mode != StatMode::FILE {
+            return -1;
+        }
+        match read_file(&file, offset, len) {
+            Some(frames) => frames,
+            None => return -1,
+        }
+    };
+    let mut inner = process.inner_exclusive_access();
+    let start_va = if flags.contains(MmapFlags::FIXED) {
+        let start_va = VirtAddr::from(addr);
+        if start_va.0 != addr || !start_va.aligned() || addr + len > MMAP_END {
+            return -1;
+        }
+        // whatever was mapped there before goes away
+        if !inner
+            .memory_set
+            .remove_range(start_va.floor(), VirtAddr::from(addr + len).ceil())
+        {
+            return -1;
+        }
+        start_va
+    } else {
+        match inner.memory_set.find_free_area(VirtAddr::from(addr), len) {
+            Some(start_va) => start_va,
+            None => return -1,
+        }
+    };
+    let end_va = VirtAddr::from(start_va.0 + len);
+    let area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+    // the pages past the file are backed on the first touch
+    if !inner.memory_set.push_with_frames(area, frames) {
+        return -1;
+    }
+    start_va.0 as isize
+}
+
+/// Move the end of the heap to `addr` if possible and return the end.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    inner.memory_set.set_brk(addr);
+    inner.memory_set.brk() as isize
+}
+
+/// Grow or shrink the heap by `increment` bytes and return its old end.
+pub fn sys_sbrk(increment: isize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_brk = inner.memory_set.brk();
+    match old_brk.checked_add_signed(increment) {
+        Some(new_brk) if inner.memory_set.set_brk(new_brk) => old_brk as isize,
+        _ => -1,
+    }
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let start_va = VirtAddr::from(addr);
+    if len == 0 || start_va.0 != addr || !start_va.aligned() || len > MMAP_END {
+        return -1;
+    }
+    let end_va = VirtAddr::from(addr + len);
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner
+        .memory_set
+        .remove_range(start_va.floor(), end_va.ceil())
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let start_va = VirtAddr::from(addr);
+    if len == 0 || start_va.0 != addr || !start_va.aligned() || len > MMAP_END {
+        return -1;
+    }
+    let map_perm = match map_permission(prot) {
+        Some(map_perm) => map_perm,
+        None => return -1,
+    };
+    let end_va = VirtAddr::from(addr + len);
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner
+        .memory_set
+        .protect_range(start_va.floor(), end_va.ceil(), map_perm)
+    {
+        0
+    } else {
+        -1
+    }
+}
@@ -29,7 +29,10 @@ pub fn sys_getpid() -> isize {
 
 pub fn sys_fork() -> isize {
     let current_process = current_process();
-    let new_process = current_process.fork();
+    let new_process = match current_process.fork() {
+        Some(new_process) => new_process,
+        None => return -1,
+    };
     let new_pid = new_process.getpid();
     // modify trap context of new_task, because it returns immediately after switching
     let new_process_inner = new_process.inner_exclusive_access();
@@ -43,23 +46,37 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = match translated_str(token, path) {
+        Some(path) => path,
+        None => return -1,
+    };
     let mut args_vec: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
+        let arg_str_ptr = match translated_ref(token, args) {
+            Some(arg_str_ptr) => *arg_str_ptr,
+            None => return -1,
+        };
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        match translated_str(token, arg_str_ptr as *const u8) {
+            Some(arg) => args_vec.push(arg),
+            None => return -1,
+        }
         unsafe {
             args = args.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    let process = current_process();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if let Some(app_inode) = open_file(cwd.as_str(), path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
-        let process = current_process();
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        if !process.exec(all_data.as_slice(), args_vec) {
+            // the old image is gone, so there is nothing to return to
+            process.inner_exclusive_access().signals |= SignalFlags::SIGSEGV;
+            return -1;
+        }
         // return argc because cx.x[10] will be covered with it later
         argc as isize
     } else {
@@ -95,8 +112,16 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child PCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
+        let token = inner.memory_set.token();
+        // writing to user space may need the PCB for copy-on-write
+        drop(inner);
+        match translated_refmut(token, exit_code_ptr) {
+            Some(exit_code_ref) => {
+                *exit_code_ref = exit_code;
+                found_pid as isize
+            }
+            None => -1,
+        }
     } else {
         -2
     }
@@ -2,6 +2,7 @@ use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
 use crate::sync::UPIntrFreeCell;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 
 pub struct TaskManager {
@@ -28,6 +29,8 @@ lazy_static! {
         unsafe { UPIntrFreeCell::new(TaskManager::new()) };
     pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
         unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// The pid of the process which swapped out a page for `reclaim_frame` last.
+    static ref RECLAIM_HAND: UPIntrFreeCell<usize> = unsafe { UPIntrFreeCell::new(0) };
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -54,6 +57,29 @@ pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
 
+/// Have a process swap out one of its pages and return whether one did.
+/// The processes take turns. Those whose address space is locked are left
+/// alone, the kernel is in the middle of changing it.
+pub fn reclaim_frame() -> bool {
+    let processes: Vec<Arc<ProcessControlBlock>> = match PID2PCB.try_exclusive_access() {
+        Some(map) => map.values().cloned().collect(),
+        None => return false,
+    };
+    let hand = *RECLAIM_HAND.exclusive_access();
+    let (before, after): (Vec<_>, Vec<_>) = processes
+        .into_iter()
+        .partition(|process| process.getpid() <= hand);
+    for process in after.into_iter().chain(before) {
+        if let Some(mut inner) = process.try_inner_exclusive_access() {
+            if inner.memory_set.swap_out() {
+                *RECLAIM_HAND.exclusive_access() = process.getpid();
+                return true;
+            }
+        }
+    }
+    false
+}
+
 pub fn remove_from_pid2process(pid: usize) {
     let mut map = PID2PCB.exclusive_access();
     if map.remove(&pid).is_none() {
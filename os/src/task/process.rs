@@ -3,6 +3,7 @@ use super::manager::insert_into_pid2process;
 use super::TaskControlBlock;
 use super::{add_task, SignalFlags};
 use super::{pid_alloc, PidHandle};
+use crate::config::{FORK_SPARE_FRAMES, KERNEL_STACK_SIZE, PAGE_SIZE};
 use crate::fs::{File, Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
 use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
@@ -26,6 +27,8 @@ pub struct ProcessControlBlockInner {
     pub children: Vec<Arc<ProcessControlBlock>>,
     pub exit_code: i32,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// Absolute path of the working directory.
+    pub cwd: String,
     pub signals: SignalFlags,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
@@ -71,6 +74,10 @@ impl ProcessControlBlock {
         self.inner.exclusive_access()
     }
 
+    pub fn try_inner_exclusive_access(&self) -> Option<UPIntrRefMut<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn new(elf_data: &[u8]) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
@@ -93,6 +100,7 @@ impl ProcessControlBlock {
                         // 2 -> stderr
                         Some(Arc::new(Stdout)),
                     ],
+                    cwd: String::from("/"),
                     signals: SignalFlags::empty(),
                     tasks: Vec::new(),
                     task_res_allocator: RecycleAllocator::new(),
@@ -131,8 +139,9 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    /// Only support processes with a single thread. False if the arguments
+    /// do not fit on the new user stack.
+    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) -> bool {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
@@ -147,28 +156,11 @@ impl ProcessControlBlock {
         task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
         // push arguments on user stack
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(new_token, p as *mut u8) = 0;
-        }
+        let user_sp = task_inner.res.as_mut().unwrap().ustack_top();
+        let (mut user_sp, argv_base) = match push_args(new_token, user_sp, &args) {
+            Some(pushed) => pushed,
+            None => return false,
+        };
         // make the user_sp aligned to 8B for k210 platform
         user_sp -= user_sp % core::mem::size_of::<usize>();
         // initialize trap_cx
@@ -182,14 +174,23 @@ impl ProcessControlBlock {
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        true
     }
 
-    /// Only support processes with a single thread.
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// Only support processes with a single thread. `None` if there is
+    /// not enough memory for the child.
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
         let mut parent = self.inner_exclusive_access();
         assert_eq!(parent.thread_count(), 1);
-        // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        // the parent cannot give up pages while it is locked, so it makes
+        // room for the child and its kernel stack up front
+        let frames = parent.memory_set.fork_frames() + KERNEL_STACK_SIZE / PAGE_SIZE;
+        if !parent.memory_set.reserve_frames(frames + FORK_SPARE_FRAMES) {
+            return None;
+        }
+        // clone parent's memory_set including trampoline/ustacks/trap_cxs,
+        // sharing the user pages copy-on-write
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set);
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
@@ -212,6 +213,7 @@ impl ProcessControlBlock {
                     children: Vec::new(),
                     exit_code: 0,
                     fd_table: new_fd_table,
+                    cwd: parent.cwd.clone(),
                     signals: SignalFlags::empty(),
                     tasks: Vec::new(),
                     task_res_allocator: RecycleAllocator::new(),
@@ -249,10 +251,36 @@ impl ProcessControlBlock {
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
         // add this thread to scheduler
         add_task(task);
-        child
+        Some(child)
     }
 
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 }
+
+/// Push `args` and the `argv` array pointing at them below `user_sp`,
+/// returning the new `user_sp` and `argv`.
+fn push_args(token: usize, mut user_sp: usize, args: &[String]) -> Option<(usize, usize)> {
+    user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
+    let argv_base = user_sp;
+    // looked up again for each write, as the page may be swapped out between
+    let argv = |arg: usize| {
+        translated_refmut(
+            token,
+            (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
+        )
+    };
+    *argv(args.len())? = 0;
+    for i in 0..args.len() {
+        user_sp -= args[i].len() + 1;
+        *argv(i)? = user_sp;
+        let mut p = user_sp;
+        for c in args[i].as_bytes() {
+            *translated_refmut(token, p as *mut u8)? = *c;
+            p += 1;
+        }
+        *translated_refmut(token, p as *mut u8)? = 0;
+    }
+    Some((user_sp, argv_base))
+}
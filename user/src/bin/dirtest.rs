@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    chdir, close, dirents, getcwd, getdents, link, mkdir, open, read, readlink, rename, renameat2,
+    rmdir, symlink, unlink, write, OpenFlags, DT_DIR, RENAME_NOREPLACE,
+};
+
+fn cwd(buf: &mut [u8]) -> &str {
+    let len = getcwd(buf);
+    assert!(len > 0);
+    core::str::from_utf8(&buf[..len as usize]).unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 64];
+    assert_eq!(cwd(&mut buf), "/");
+    assert_eq!(mkdir("dirtest_home\0"), 0);
+    assert_eq!(mkdir("dirtest_home\0"), -1);
+    assert_eq!(mkdir("/dirtest_home/a\0"), 0);
+
+    // create a file through a nested absolute path
+    let test_str = "nested hello";
+    let fd = open(
+        "/dirtest_home/a/notes\0",
+        OpenFlags::CREATE | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    // and read it back relative to the working directory
+    assert_eq!(chdir("dirtest_home/a\0"), 0);
+    assert_eq!(cwd(&mut buf), "/dirtest_home/a");
+    let fd = open("./notes\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 32];
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+
+    assert_eq!(chdir("notes\0"), -1);
+    assert_eq!(chdir("../..\0"), 0);
+    assert_eq!(cwd(&mut buf), "/");
+    assert_eq!(getcwd(&mut buf[..1]), -1);
+
+    // only empty directories can be removed
+    assert_eq!(rmdir("dirtest_home\0"), -1);
+    assert_eq!(mkdir("dirtest_home/b\0"), 0);
+    assert_eq!(rmdir("dirtest_home/b\0"), 0);
+    assert_eq!(chdir("dirtest_home/b\0"), -1);
+
+    // the removed entry no longer shows up in a listing
+    let fd = open("dirtest_home\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut dirent_buf = [0u8; 128];
+    assert_eq!(getdents(fd, &mut dirent_buf[..8]), -1);
+    let size = getdents(fd, &mut dirent_buf);
+    assert!(size > 0);
+    let mut names = dirents(&dirent_buf[..size as usize]).map(|dirent| {
+        assert_eq!(dirent.type_, DT_DIR);
+        dirent.name
+    });
+    assert_eq!(names.next(), Some("."));
+    assert_eq!(names.next(), Some(".."));
+    assert_eq!(names.next(), Some("a"));
+    assert_eq!(names.next(), None);
+    assert_eq!(getdents(fd, &mut dirent_buf), 0);
+    close(fd);
+
+    // symbolic links are followed on open and chdir
+    assert_eq!(symlink("a/notes\0", "dirtest_home/notes_link\0"), 0);
+    assert_eq!(symlink("/dirtest_home/a\0", "dirtest_home/a_link\0"), 0);
+    let fd = open("dirtest_home/a_link/../notes_link\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+    let len = readlink("dirtest_home/notes_link\0", &mut buffer);
+    assert_eq!(&buffer[..len as usize], b"a/notes");
+    assert_eq!(readlink("dirtest_home/a/notes\0", &mut buffer), -1);
+    assert_eq!(chdir("dirtest_home/a_link\0"), 0);
+    assert_eq!(cwd(&mut buf), "/dirtest_home/a");
+    assert_eq!(chdir("/\0"), 0);
+    // ".." after a link leaves the directory it points at
+    assert_eq!(symlink("dirtest_home/a\0", "dirtest_a_link\0"), 0);
+    assert_eq!(chdir("dirtest_a_link/..\0"), 0);
+    assert_eq!(cwd(&mut buf), "/dirtest_home");
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(unlink("dirtest_a_link\0"), 0);
+    // and a loop is cut off
+    assert_eq!(symlink("loop\0", "dirtest_home/loop\0"), 0);
+    assert_eq!(open("dirtest_home/loop\0", OpenFlags::RDONLY), -1);
+    assert_eq!(unlink("dirtest_home/loop\0"), 0);
+    assert_eq!(unlink("dirtest_home/a_link\0"), 0);
+    assert_eq!(unlink("dirtest_home/notes_link\0"), 0);
+
+    // rename across directories and back, refusing to replace if asked to
+    assert_eq!(rename("dirtest_home/a/notes\0", "dirtest_home/moved\0"), 0);
+    assert_eq!(open("dirtest_home/a/notes\0", OpenFlags::RDONLY), -1);
+    assert_eq!(mkdir("dirtest_home/a/notes\0"), 0);
+    assert_eq!(rename("dirtest_home/moved\0", "dirtest_home/a/notes\0"), -1);
+    assert_eq!(rmdir("dirtest_home/a/notes\0"), 0);
+    let fd = open(
+        "dirtest_home/a/notes\0",
+        OpenFlags::CREATE | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(
+        renameat2(
+            "dirtest_home/moved\0",
+            "dirtest_home/a/notes\0",
+            RENAME_NOREPLACE
+        ),
+        -1
+    );
+    assert_eq!(rename("dirtest_home/moved\0", "dirtest_home/a/notes\0"), 0);
+    assert_eq!(rename("dirtest_home\0", "dirtest_home/a/inside\0"), -1);
+
+    // clean up so that the test can be run again
+    assert_eq!(unlink("dirtest_home/a\0"), -1);
+    assert_eq!(rmdir("dirtest_home/a/notes\0"), -1);
+    assert_eq!(link("dirtest_home/a/notes\0", "dirtest_home/notes\0"), 0);
+    assert_eq!(link("dirtest_home/a\0", "dirtest_home/a2\0"), -1);
+    assert_eq!(unlink("dirtest_home/a/notes\0"), 0);
+    assert_eq!(unlink("dirtest_home/a/notes\0"), -1);
+    let fd = open("dirtest_home/notes\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap());
+    assert_eq!(unlink("dirtest_home/notes\0"), 0);
+    assert_eq!(rmdir("dirtest_home/a\0"), 0);
+    assert_eq!(rmdir("dirtest_home\0"), 0);
+    println!("dirtest passed!");
+    0
+}
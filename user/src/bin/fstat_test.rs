@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, link, open, pipe, sleep, unlink, write, OpenFlags, Stat, StatMode};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut st = Stat::new();
+    assert_eq!(fstat(0, &mut st), 0);
+    assert_eq!(st.mode, StatMode::CHR);
+    assert_eq!(fstat(42, &mut st), -1);
+
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(fstat(pipe_fd[0], &mut st), 0);
+    assert_eq!(st.mode, StatMode::FIFO);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    let fd = open("fstat_file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!(st.mode, StatMode::FILE);
+    assert_eq!(st.size, 0);
+    assert_eq!(st.nlink, 1);
+    let created = st.mtime;
+
+    // writing updates the size and the modification time
+    sleep(10);
+    write(fd, b"fstat");
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!(st.size, 5);
+    assert!(st.mtime > created);
+    let ino = st.ino;
+
+    // a hard link shares the inode and bumps the link count
+    assert_eq!(link("fstat_file\0", "fstat_link\0"), 0);
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!(st.nlink, 2);
+    let link_fd = open("fstat_link\0", OpenFlags::RDONLY);
+    assert!(link_fd > 0);
+    assert_eq!(fstat(link_fd as usize, &mut st), 0);
+    assert_eq!(st.ino, ino);
+    close(link_fd as usize);
+    close(fd);
+    assert_eq!(unlink("fstat_link\0"), 0);
+    assert_eq!(unlink("fstat_file\0"), 0);
+
+    let fd = open("/\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(fstat(fd as usize, &mut st), 0);
+    assert_eq!(st.mode, StatMode::DIR);
+    close(fd as usize);
+    println!("fstat_test passed!");
+    0
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // far more than the heap started out with
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..(1 << 18) {
+        v.push(i);
+    }
+    assert!(v.iter().enumerate().all(|(i, &x)| i == x));
+    drop(v);
+
+    // brk(0) just tells where the heap ends
+    let end = brk(0);
+    assert!(end > 0);
+    assert_eq!(sbrk(0), end);
+    assert_eq!(sbrk(2 * PAGE_SIZE as isize), end);
+    let page = unsafe { core::slice::from_raw_parts_mut(end as *mut u8, 2 * PAGE_SIZE) };
+    assert!(page.iter().all(|&b| b == 0));
+    page[2 * PAGE_SIZE - 1] = 42;
+    assert_eq!(brk(end as usize), end);
+    assert_eq!(sbrk(0), end);
+    // the heap cannot reach the stacks
+    assert_eq!(sbrk(isize::MAX), -1);
+    assert_eq!(sbrk(0), end);
+    println!("heap_test passed!");
+    0
+}
@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{link, symlink};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    // the arguments are `\0`-terminated on the user stack
+    let ret = match argc {
+        3 => link(argv[1], argv[2]),
+        4 if argv[1] == "-s" => symlink(argv[2], argv[3]),
+        _ => {
+            println!("usage: ln [-s] TARGET LINK_NAME");
+            return -1;
+        }
+    };
+    if ret == -1 {
+        println!("ln: cannot create link {}", argv[argc - 1]);
+        return -1;
+    }
+    0
+}
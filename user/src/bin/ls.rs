@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dirents, getdents, open, OpenFlags, DT_DIR, DT_LNK};
+
+fn list(path: &str) -> i32 {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd == -1 {
+        println!("ls: cannot access {}", path);
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 512];
+    let mut exit_code = 0;
+    loop {
+        let size = getdents(fd, &mut buf);
+        if size == -1 {
+            println!("ls: cannot list {}", path);
+            exit_code = -1;
+            break;
+        }
+        if size == 0 {
+            break;
+        }
+        for dirent in dirents(&buf[..size as usize]) {
+            if dirent.name.starts_with('.') {
+                continue;
+            }
+            match dirent.type_ {
+                DT_DIR => println!("{}/", dirent.name),
+                DT_LNK => println!("{}@", dirent.name),
+                _ => println!("{}", dirent.name),
+            }
+        }
+    }
+    close(fd);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    // the arguments are `\0`-terminated on the user stack
+    if argc < 2 {
+        return list(".\0");
+    }
+    let mut exit_code = 0;
+    for path in &argv[1..] {
+        if argc > 2 {
+            println!("{}:", path);
+        }
+        if list(path) == -1 {
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
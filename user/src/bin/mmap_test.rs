@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, getcwd, mmap, mprotect, munmap, open, pipe, unlink, waitpid, write,
+    MmapFlags, MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Run `f` in a child and return its exit code.
+fn in_child(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+fn page(addr: usize) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let anonymous = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+
+    // anonymous pages start out zeroed
+    let addr = mmap(0, 4 * PAGE_SIZE, rw, anonymous, 0, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    assert!(page(addr + PAGE_SIZE).iter().all(|&b| b == 0));
+    page(addr + 3 * PAGE_SIZE)[7] = 42;
+
+    // the mapping stays private to each side of a fork
+    static mut SHARED_ADDR: usize = 0;
+    unsafe { SHARED_ADDR = addr };
+    assert_eq!(
+        in_child(|| {
+            let addr = unsafe { SHARED_ADDR };
+            assert_eq!(page(addr + 3 * PAGE_SIZE)[7], 42);
+            page(addr + 3 * PAGE_SIZE)[7] = 24;
+        }),
+        0
+    );
+    assert_eq!(page(addr + 3 * PAGE_SIZE)[7], 42);
+
+    // read-only pages fault on write, unmapped ones on any access
+    assert_eq!(mprotect(addr, PAGE_SIZE, MmapProt::READ), 0);
+    assert_eq!(in_child(|| page(unsafe { SHARED_ADDR })[0] = 1), -11);
+    // nor does the kernel write to them on the user's behalf
+    assert_eq!(getcwd(page(addr)), -1);
+    let fds = unsafe { core::slice::from_raw_parts_mut(addr as *mut usize, 2) };
+    assert_eq!(pipe(fds), -1);
+    assert_eq!(page(addr)[0], 0);
+    assert_eq!(mprotect(addr, PAGE_SIZE, MmapProt::NONE), -1);
+    assert_eq!(munmap(addr + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(
+        in_child(|| {
+            let _ = unsafe { (SHARED_ADDR as *const u8).add(PAGE_SIZE).read_volatile() };
+        }),
+        -11
+    );
+    // the pages around the hole are still there
+    assert_eq!(page(addr)[0], 0);
+    assert_eq!(page(addr + 3 * PAGE_SIZE)[7], 42);
+    assert_eq!(munmap(addr, 4 * PAGE_SIZE), 0);
+
+    // a file mapping starts with the file and is zeroed past its end
+    let fd = open("mmap_file\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, b"hello, mmap!");
+    let file_addr = mmap(0, 2 * PAGE_SIZE, rw, MmapFlags::PRIVATE, fd, 0);
+    assert!(file_addr > 0);
+    let file_addr = file_addr as usize;
+    assert_eq!(&page(file_addr)[..12], b"hello, mmap!");
+    assert!(page(file_addr)[12..].iter().all(|&b| b == 0));
+    assert!(page(file_addr + PAGE_SIZE).iter().all(|&b| b == 0));
+    page(file_addr)[0] = b'j';
+    assert_eq!(munmap(file_addr, 2 * PAGE_SIZE), 0);
+    assert_eq!(mmap(0, PAGE_SIZE, rw, MmapFlags::SHARED, fd, 0), -1);
+    close(fd);
+    assert_eq!(unlink("mmap_file\0"), 0);
+
+    // a fixed mapping replaces what was there
+    let addr = mmap(0, 2 * PAGE_SIZE, rw, anonymous, 0, 0) as usize;
+    page(addr)[0] = 1;
+    let fixed = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::FIXED;
+    assert_eq!(mmap(addr, PAGE_SIZE, rw, fixed, 0, 0), addr as isize);
+    assert_eq!(page(addr)[0], 0);
+    assert_eq!(munmap(addr, 2 * PAGE_SIZE), 0);
+    println!("mmap_test passed!");
+    0
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{close, fstat, open, rename, OpenFlags, Stat, StatMode};
+
+fn is_dir(path: &str) -> bool {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd == -1 {
+        return false;
+    }
+    let mut st = Stat::new();
+    let ret = fstat(fd as usize, &mut st);
+    close(fd as usize);
+    ret == 0 && st.mode == StatMode::DIR
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: mv SOURCE DEST");
+        return -1;
+    }
+    let (source, dest) = (argv[1], argv[2]);
+    // the arguments are `\0`-terminated on the user stack
+    let mut target = String::from(dest);
+    if is_dir(dest) {
+        // move into the directory, keeping the name
+        let name = source.trim_end_matches('/').rsplit('/').next().unwrap();
+        target = format!("{}/{}", dest.trim_end_matches('/'), name);
+    }
+    target.push('\0');
+    if rename(source, target.as_str()) == -1 {
+        println!("mv: cannot move {} to {}", source, dest);
+        return -1;
+    }
+    0
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::unlink;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: rm FILE...");
+        return -1;
+    }
+    let mut exit_code = 0;
+    // the arguments are `\0`-terminated on the user stack
+    for path in &argv[1..] {
+        if unlink(path) == -1 {
+            println!("rm: cannot remove {}", path);
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fdatasync, fsync, ftruncate, lseek, open, pipe, pread, pwrite, read, sync, unlink,
+    write, OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("seektest_file\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"hello, world"), 12);
+
+    // rewind and read back
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut buf = [0u8; 16];
+    assert_eq!(read(fd, &mut buf[..5]), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(lseek(fd, 2, SEEK_CUR), 7);
+    assert_eq!(read(fd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(lseek(fd, -5, SEEK_END), 7);
+    assert_eq!(lseek(fd, -8, SEEK_CUR), -1);
+    assert_eq!(lseek(fd, -1, SEEK_SET), -1);
+
+    // positioned I/O leaves the offset alone
+    assert_eq!(pwrite(fd, b"W", 7), 1);
+    assert_eq!(pread(fd, &mut buf[..5], 0), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 7);
+    assert_eq!(read(fd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"World");
+    assert_eq!(pread(fd, &mut buf, 12), 0);
+    // nothing goes past the largest file easy-fs can hold
+    assert_eq!(pwrite(fd, b"W", (4 << 30) + 10), -1);
+    assert_eq!(lseek(fd, 4 << 30, SEEK_SET), -1);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+
+    // truncation keeps the offset, growing again reads back zeroes
+    assert_eq!(ftruncate(fd, 5), 0);
+    assert_eq!(lseek(fd, 0, SEEK_END), 5);
+    assert_eq!(ftruncate(fd, 8), 0);
+    assert_eq!(pread(fd, &mut buf, 0), 8);
+    assert_eq!(&buf[..8], b"hello\0\0\0");
+    assert_eq!(ftruncate(fd, u32::MAX as usize - 10), -1);
+    assert_eq!(fdatasync(fd), 0);
+    assert_eq!(fsync(fd), 0);
+    close(fd);
+    assert_eq!(sync(), 0);
+    assert_eq!(unlink("seektest_file\0"), 0);
+
+    // pipes cannot seek
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+    assert_eq!(pwrite(pipe_fd[1], b"x", 0), -1);
+    assert_eq!(fsync(pipe_fd[1]), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("seektest passed!");
+    0
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, munmap, waitpid, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+/// More than the memory left to user programs.
+const PAGES: usize = 128 * 1024 * 1024 / PAGE_SIZE;
+
+fn word(addr: usize, page: usize) -> &'static mut usize {
+    unsafe { &mut *((addr + page * PAGE_SIZE) as *mut usize) }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let anonymous = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    let addr = mmap(0, PAGES * PAGE_SIZE, rw, anonymous, 0, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    for page in 0..PAGES {
+        *word(addr, page) = page;
+    }
+    // the early pages are on the swap device by now
+    for page in 0..PAGES {
+        assert_eq!(*word(addr, page), page);
+    }
+
+    // the child finds them too, and its changes are its own
+    let pid = fork();
+    assert!(pid >= 0);
+    if pid == 0 {
+        for page in (0..PAGES).step_by(7) {
+            assert_eq!(*word(addr, page), page);
+            *word(addr, page) = 0;
+        }
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for page in 0..PAGES {
+        assert_eq!(*word(addr, page), page);
+    }
+    assert_eq!(munmap(addr, PAGES * PAGE_SIZE), 0);
+    println!("swap_test passed!");
+    0
+}
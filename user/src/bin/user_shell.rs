@@ -16,7 +16,7 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{chdir, close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -90,6 +90,23 @@ pub fn main() -> i32 {
                         .iter()
                         .map(|&cmd| ProcessArguments::new(cmd))
                         .collect();
+                    // cd has to run in the shell itself rather than in a child
+                    if process_arguments_list.len() == 1
+                        && process_arguments_list[0]
+                            .args_copy
+                            .first()
+                            .map(String::as_str)
+                            == Some("cd\0")
+                    {
+                        let args_copy = &process_arguments_list[0].args_copy;
+                        let dir = args_copy.get(1).map_or("/\0", String::as_str);
+                        if chdir(dir) == -1 {
+                            println!("cd: no such directory: {}", dir.trim_end_matches('\0'));
+                        }
+                        line.clear();
+                        print!("{}", LINE_START);
+                        continue;
+                    }
                     let mut valid = true;
                     for (i, process_args) in process_arguments_list.iter().enumerate() {
                         if i == 0 {
@@ -172,8 +189,13 @@ pub fn main() -> i32 {
                                     close(pipe_fd[0]);
                                     close(pipe_fd[1]);
                                 }
-                                // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                // execute new application, falling back to the root
+                                // directory where the apps are packed
+                                let mut app_path = String::from("/");
+                                app_path.push_str(args_copy[0].as_str());
+                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1
+                                    && exec(app_path.as_str(), args_addr.as_slice()) == -1
+                                {
                                     println!("Error when executing!");
                                     return -4;
                                 }
@@ -1,4 +1,5 @@
 use super::*;
+use core::convert::TryInto;
 
 bitflags! {
     pub struct OpenFlags: u32 {
@@ -10,6 +11,87 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+        const LINK = 0o120000;
+        const SOCK = 0o140000;
+    }
+}
+
+/// Mirrors the kernel's `Stat` filled in by `fstat`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: StatMode,
+    pub nlink: u32,
+    pub size: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 0,
+            size: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+impl Default for Stat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const AT_REMOVEDIR: u32 = 0x200;
+pub const RENAME_NOREPLACE: u32 = 1;
+
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// One `linux_dirent64` record filled in by `getdents`.
+pub struct Dirent<'a> {
+    pub ino: u64,
+    pub type_: u8,
+    pub name: &'a str,
+}
+
+/// Walk the records in `buf`, which holds what one `getdents` call returned.
+pub fn dirents(buf: &[u8]) -> impl Iterator<Item = Dirent<'_>> {
+    let mut rest = buf;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let ino = u64::from_le_bytes(rest[0..8].try_into().unwrap());
+        let reclen = u16::from_le_bytes(rest[16..18].try_into().unwrap()) as usize;
+        let type_ = rest[18];
+        let name = &rest[19..reclen];
+        let name_len = name.iter().position(|b| *b == 0).unwrap();
+        let name = core::str::from_utf8(&name[..name_len]).unwrap();
+        rest = &rest[reclen..];
+        Some(Dirent { ino, type_, name })
+    })
+}
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -19,6 +101,9 @@ pub fn open(path: &str, flags: OpenFlags) -> isize {
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st)
+}
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
@@ -28,3 +113,62 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread(fd, buf, offset)
+}
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    sys_pwrite(fd, buf, offset)
+}
+pub fn ftruncate(fd: usize, length: usize) -> isize {
+    sys_ftruncate(fd, length)
+}
+pub fn sync() -> isize {
+    sys_sync()
+}
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+/// Like `fsync`, but metadata other than the size may not be written.
+pub fn fdatasync(fd: usize) -> isize {
+    sys_fdatasync(fd)
+}
+/// Read the next entries of the directory `fd` into `buf`; 0 at the end.
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents64(fd, buf)
+}
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(path)
+}
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(old_path, new_path)
+}
+pub fn symlink(target: &str, path: &str) -> isize {
+    sys_symlinkat(target, path)
+}
+/// Read the target of a symbolic link into `buf`, without a trailing `\0`.
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+    sys_readlinkat(path, buf)
+}
+/// Move `old_path` to `new_path`, replacing an existing file there.
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    sys_renameat2(old_path, new_path, 0)
+}
+pub fn renameat2(old_path: &str, new_path: &str, flags: u32) -> isize {
+    sys_renameat2(old_path, new_path, flags)
+}
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(path, 0)
+}
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(path, AT_REMOVEDIR)
+}
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+/// Write the working directory followed by `\0` into `buf` and return its length.
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
@@ -8,6 +8,7 @@ pub mod console;
 mod file;
 mod io;
 mod lang_items;
+mod memory;
 mod net;
 mod sync;
 mod syscall;
@@ -19,19 +20,50 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
 pub use file::*;
 pub use io::*;
+pub use memory::*;
 pub use net::*;
 pub use sync::*;
 use syscall::*;
 pub use task::*;
 
-const USER_HEAP_SIZE: usize = 32768;
+/// The heap grows by at least this much at a time.
+const USER_HEAP_GROWTH: usize = 32768;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// A `LockedHeap` asking the kernel for more memory when it runs out.
+struct GrowingHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        // buddy blocks are aligned to their size, twice that holds one for sure
+        let size = layout
+            .size()
+            .max(layout.align())
+            .next_power_of_two()
+            .max(USER_HEAP_GROWTH)
+            * 2;
+        let start = sbrk(size as isize);
+        if start < 0 {
+            return ptr;
+        }
+        self.0
+            .lock()
+            .add_to_heap(start as usize, start as usize + size);
+        self.0.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowingHeap = GrowingHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -41,10 +73,6 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
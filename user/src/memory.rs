@@ -0,0 +1,47 @@
+use super::*;
+
+bitflags! {
+    pub struct MmapProt: usize {
+        const NONE = 0;
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+/// Map `len` bytes of `fd` from `offset`, or of zeros with
+/// `MmapFlags::ANONYMOUS`, and return the start or -1.
+pub fn mmap(
+    addr: usize,
+    len: usize,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits(), flags.bits(), fd, offset)
+}
+/// Move the end of the heap to `addr` if possible and return the end.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+/// Grow or shrink the heap by `increment` bytes and return its old end,
+/// or -1 if it cannot.
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits())
+}